@@ -0,0 +1,56 @@
+//! Output and input latency of a panel thread, measured against the mock transport.
+//!
+//! Run with `cargo bench --bench latency`.
+
+use std::sync::mpsc;
+use std::time::{Duration, Instant};
+use flightpanels_rs::{InputData, RadioPanelCommands, RadioPanelInputs};
+use flightpanels_rs::radio_panel::RadioPanel;
+use flightpanels_rs::transport::MockTransport;
+
+const ROUNDS: u32 = 500;
+const TIMEOUT: Duration = Duration::from_secs(1);
+
+fn report(name: &str, mut samples: Vec<Duration>) {
+    samples.sort();
+    let total: Duration = samples.iter().sum();
+    println!("{:<8} min {:>9.3?}  median {:>9.3?}  p99 {:>9.3?}  max {:>9.3?}  mean {:>9.3?}",
+        name,
+        samples[0],
+        samples[samples.len() / 2],
+        samples[samples.len() * 99 / 100],
+        samples[samples.len() - 1],
+        total / samples.len() as u32);
+}
+
+fn main() {
+    let mock = MockTransport::new();
+    let (tx, rx) = mpsc::channel();
+    let (cmd_tx, cmd_rx) = mpsc::channel();
+    RadioPanel::receive_from(mock.clone(), tx, cmd_rx);
+    mock.next_feature_report(TIMEOUT).expect("no initial report");
+
+    // command sent until the report reaches the device
+    let mut output = Vec::new();
+    for round in 0..ROUNDS {
+        let start = Instant::now();
+        cmd_tx.send(RadioPanelCommands::SetUpperActiveFrequency(100.0 + round as f32)).unwrap();
+        mock.next_feature_report(TIMEOUT).expect("no report sent");
+        output.push(start.elapsed());
+    }
+
+    // report read until the decoded input is received
+    let mut input = Vec::new();
+    for round in 0..ROUNDS {
+        let inputs = RadioPanelInputs::new().with_swap1(round % 2 == 0);
+        let start = Instant::now();
+        mock.push_input(&u32::from(inputs).to_le_bytes());
+        match rx.recv_timeout(TIMEOUT) {
+            Ok(InputData::RadioInputData(..)) => input.push(start.elapsed()),
+            other => panic!("unexpected {:?}", other)
+        }
+    }
+
+    report("output", output);
+    report("input", input);
+}
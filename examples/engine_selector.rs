@@ -0,0 +1,41 @@
+use flightpanels_rs::{Event, EventDecoder, Flightpanels, InputData, Selector};
+use flightpanels_rs::switch_panel::{EngineSelection, GearLedsStates, LedColors, OutputCommands};
+
+fn main() {
+    let panels = Flightpanels::new().expect("could not initialize hidapi");
+    let mut decoder = EventDecoder::new();
+
+    loop {
+        match panels.inputs().recv() {
+            Ok(rec) => {
+                match &rec {
+                    InputData::FIPInputData(_, data) => println!("{:#?}", data),
+                    InputData::DeviceError(device, error) => println!("{}: {}", device, error),
+                    InputData::DeviceConnected(device) => println!("{} connected", device),
+                    InputData::DeviceDisconnected(device) => println!("{} disconnected", device),
+                    _ => ()
+                }
+                for event in decoder.decode(&rec) {
+                    if let Event::SelectorChanged(Selector::Engine(selection)) = event.event {
+                        let command = match selection {
+                            EngineSelection::LEFT => Some(OutputCommands::SetLeftLedTo(LedColors::Green)),
+                            EngineSelection::RIGHT => Some(OutputCommands::SetRightLedTo(LedColors::Green)),
+                            EngineSelection::BOTH => Some(OutputCommands::SetUpLedTo(LedColors::Green)),
+                            EngineSelection::OFF => Some(OutputCommands::SetAllLedsTo(LedColors::Off)),
+                            EngineSelection::START => Some(OutputCommands::SetLeds((GearLedsStates::LEFT_GREEN | GearLedsStates::UP_YELLOW | GearLedsStates::RIGHT_RED).bits())),
+                            EngineSelection::Invalid => None
+                        };
+                        if let Some(command) = command {
+                            let _ = panels.switch_panel().send(command);
+                        }
+                    }
+                    println!("{}: {:?}", event.device, event.event);
+                }
+            },
+            Err(e) => {
+                println!("Error {}", e);
+                break;
+            }
+        }
+    }
+}
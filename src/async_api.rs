@@ -0,0 +1,320 @@
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use futures_core::Stream;
+use futures_sink::Sink;
+use tokio::sync::mpsc::{unbounded_channel, UnboundedReceiver, UnboundedSender};
+use crate::{DeviceId, Flightpanels, InputData, PanelKind};
+use crate::{FlightInstrumentPanelCommands, MultiPanelCommands, RadioPanelCommands, SwitchPanelCommands};
+use crate::encoders::{Acceleration, Knob};
+use crate::error::{Error, Result};
+use crate::events::{EventDecoder, InputEvent};
+use crate::multi_panel::{MultiDisplay, MultiPanelOutputs};
+use crate::radio_panel::{RadioDisplay, RadioPanelOutputs};
+
+/// How often the dispatcher thread checks whether it should stop.
+const STOP_INTERVAL: Duration = Duration::from_millis(100);
+
+type Filter = Box<dyn Fn(&DeviceId) -> bool + Send>;
+
+enum Subscriber {
+    Inputs(Filter, UnboundedSender<InputData>),
+    Events(Filter, UnboundedSender<InputEvent>)
+}
+
+impl Subscriber {
+    /// Hands over what the subscriber asked for, false once its stream has been dropped.
+    fn deliver(&self, input: &InputData, events: &[InputEvent]) -> bool {
+        match self {
+            Subscriber::Inputs(filter, tx) => !filter(input.device()) || tx.send(input.clone()).is_ok(),
+            Subscriber::Events(filter, tx) => events.iter()
+                .filter(|event| filter(&event.device))
+                .all(|event| tx.send(event.clone()).is_ok()) && !tx.is_closed()
+        }
+    }
+}
+
+#[derive(Default)]
+struct Shared {
+    decoder: EventDecoder,
+    subscribers: Vec<Subscriber>
+}
+
+/// Reads a blocking input channel on its own thread and fans it out to async streams.
+///
+/// Streams end when the channel closes or the dispatcher is stopped or dropped.
+pub struct Dispatcher {
+    shared: Arc<Mutex<Shared>>,
+    running: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Dispatcher {
+    pub fn new(rx: Receiver<InputData>) -> Self {
+        let shared = Arc::new(Mutex::new(Shared::default()));
+        let running = Arc::new(AtomicBool::new(true));
+        let thread_shared = shared.clone();
+        let thread_running = running.clone();
+        let thread = thread::spawn(move || {
+            while thread_running.load(Ordering::Relaxed) {
+                match rx.recv_timeout(STOP_INTERVAL) {
+                    Ok(input) => {
+                        let mut shared = thread_shared.lock().expect("dispatcher poisoned");
+                        let events = shared.decoder.decode(&input);
+                        shared.subscribers.retain(|subscriber| subscriber.deliver(&input, &events));
+                    },
+                    Err(RecvTimeoutError::Timeout) => (),
+                    Err(RecvTimeoutError::Disconnected) => break
+                }
+            }
+            // dropping the senders ends every stream
+            thread_shared.lock().expect("dispatcher poisoned").subscribers.clear();
+        });
+        Dispatcher { shared, running, thread: Some(thread) }
+    }
+
+    fn subscribe(&self, subscriber: Subscriber) {
+        self.shared.lock().expect("dispatcher poisoned").subscribers.push(subscriber);
+    }
+
+    /// Raw snapshots and connection events of the panels `filter` accepts.
+    pub fn inputs(&self, filter: impl Fn(&DeviceId) -> bool + Send + 'static) -> InputStream<InputData> {
+        let (tx, rx) = unbounded_channel();
+        self.subscribe(Subscriber::Inputs(Box::new(filter), tx));
+        InputStream { rx }
+    }
+
+    /// Decoded events of the panels `filter` accepts.
+    pub fn events(&self, filter: impl Fn(&DeviceId) -> bool + Send + 'static) -> InputStream<InputEvent> {
+        let (tx, rx) = unbounded_channel();
+        self.subscribe(Subscriber::Events(Box::new(filter), tx));
+        InputStream { rx }
+    }
+
+    pub fn set_acceleration(&self, knob: Knob, curve: Acceleration) {
+        self.shared.lock().expect("dispatcher poisoned").decoder.set_acceleration(knob, curve);
+    }
+
+    /// Stops the thread and ends all streams.
+    pub fn stop(mut self) {
+        self.halt();
+    }
+
+    fn halt(&mut self) {
+        self.running.store(false, Ordering::Relaxed);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for Dispatcher {
+    fn drop(&mut self) {
+        self.halt();
+    }
+}
+
+/// Stream of inputs or events, dropping it unsubscribes.
+pub struct InputStream<T> {
+    rx: UnboundedReceiver<T>
+}
+
+impl<T> InputStream<T> {
+    /// The next item, `None` once the panels have been shut down.
+    pub async fn next(&mut self) -> Option<T> {
+        self.rx.recv().await
+    }
+}
+
+impl<T> Stream for InputStream<T> {
+    type Item = T;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.rx.poll_recv(cx)
+    }
+}
+
+/// Output commands of one panel as a `Sink`, it never has to wait.
+#[derive(Clone)]
+pub struct PanelSink<C> {
+    tx: mpsc::Sender<C>
+}
+
+impl<C> PanelSink<C> {
+    pub fn new(tx: mpsc::Sender<C>) -> Self {
+        PanelSink { tx }
+    }
+
+    pub async fn send(&self, command: C) -> Result<()> {
+        Ok(self.tx.send(command)?)
+    }
+}
+
+impl<C> Sink<C> for PanelSink<C> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn start_send(self: Pin<&mut Self>, command: C) -> Result<()> {
+        Ok(self.tx.send(command)?)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn poll_close(self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Result<()>> {
+        Poll::Ready(Ok(()))
+    }
+}
+
+/// [`Flightpanels`] for async code: inputs as streams, outputs as sinks and async methods.
+///
+/// Dropping it, or calling `shutdown`, stops all panel threads and ends every stream.
+pub struct AsyncFlightpanels {
+    // declared first so the streams end before the panel threads are stopped
+    dispatcher: Dispatcher,
+    panels: Flightpanels
+}
+
+impl AsyncFlightpanels {
+    pub fn new() -> Result<Self> {
+        Ok(Self::from_panels(Flightpanels::new()?))
+    }
+
+    pub fn from_panels(mut panels: Flightpanels) -> Self {
+        let dispatcher = Dispatcher::new(panels.take_inputs());
+        AsyncFlightpanels { dispatcher, panels }
+    }
+
+    /// Events of all panels.
+    pub fn events(&self) -> InputStream<InputEvent> {
+        self.dispatcher.events(|_| true)
+    }
+
+    /// Events of the panels of one model.
+    pub fn panel_events(&self, kind: PanelKind) -> InputStream<InputEvent> {
+        self.dispatcher.events(move |device| device.kind == kind)
+    }
+
+    /// Events of the panel with the given name or serial.
+    pub fn device_events(&self, device: &str) -> InputStream<InputEvent> {
+        let device = device.to_string();
+        self.dispatcher.events(move |id| id.serial == device || id.name.as_deref() == Some(device.as_str()))
+    }
+
+    /// Raw snapshots and connection events of all panels.
+    pub fn inputs(&self) -> InputStream<InputData> {
+        self.dispatcher.inputs(|_| true)
+    }
+
+    pub fn set_acceleration(&self, knob: Knob, curve: Acceleration) {
+        self.dispatcher.set_acceleration(knob, curve);
+    }
+
+    pub fn multi_panel(&self) -> PanelSink<MultiPanelCommands> {
+        PanelSink::new(self.panels.multi_panel().clone())
+    }
+
+    pub fn radio_panel(&self) -> PanelSink<RadioPanelCommands> {
+        PanelSink::new(self.panels.radio_panel().clone())
+    }
+
+    pub fn switch_panel(&self) -> PanelSink<SwitchPanelCommands> {
+        PanelSink::new(self.panels.switch_panel().clone())
+    }
+
+    pub fn flight_instrument_panel(&self) -> PanelSink<FlightInstrumentPanelCommands> {
+        PanelSink::new(self.panels.flight_instrument_panel().clone())
+    }
+
+    /// Shows `value` on a radio display, values it cannot show are rejected right away.
+    pub async fn set_radio_display(&self, display: RadioDisplay, value: f32) -> Result<()> {
+        RadioPanelOutputs::default().set_display(display, value)?;
+        let command = match display {
+            RadioDisplay::UpperActive => RadioPanelCommands::SetUpperActiveFrequency(value),
+            RadioDisplay::UpperStandby => RadioPanelCommands::SetUpperStandbyFrequency(value),
+            RadioDisplay::LowerActive => RadioPanelCommands::SetLowerActiveFrequency(value),
+            RadioDisplay::LowerStandby => RadioPanelCommands::SetLowerStandbyFrequency(value)
+        };
+        self.radio_panel().send(command).await
+    }
+
+    /// Shows `value` on a multi panel display, values it cannot show are rejected right away.
+    pub async fn set_multi_display(&self, display: MultiDisplay, value: i32) -> Result<()> {
+        MultiPanelOutputs::default().set_display(display, value)?;
+        let command = match display {
+            MultiDisplay::UpperDisplay => MultiPanelCommands::SetUpperDisplay(value),
+            MultiDisplay::LowerDisplay => MultiPanelCommands::SetLowerDisplay(value)
+        };
+        self.multi_panel().send(command).await
+    }
+
+    /// Whether shutting down switches every LED off and blanks every display.
+    pub fn set_blank_on_shutdown(&self, blank: bool) {
+        self.panels.set_blank_on_shutdown(blank);
+    }
+
+    pub fn current_state(&self, device: &DeviceId) -> Option<InputData> {
+        self.panels.current_state(device)
+    }
+
+    pub fn current_states(&self) -> Vec<InputData> {
+        self.panels.current_states()
+    }
+
+    pub fn devices(&self) -> Vec<DeviceId> {
+        self.panels.devices()
+    }
+
+    /// Stops the panel threads, every stream ends after its remaining items.
+    pub fn shutdown(self) {
+        drop(self);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::Event;
+    use crate::switch_panel::SwitchPanelInputs;
+    use crate::Switch;
+
+    #[tokio::test]
+    async fn streams() {
+        let (tx, rx) = mpsc::channel();
+        let dispatcher = Dispatcher::new(rx);
+        let mut switches = dispatcher.events(|device| device.kind == PanelKind::Switch);
+        let mut radios = dispatcher.inputs(|device| device.kind == PanelKind::Radio);
+        let dropped = dispatcher.events(|_| true);
+        drop(dropped);
+
+        let switch = DeviceId::new(PanelKind::Switch, "1");
+        let radio = DeviceId::new(PanelKind::Radio, "2");
+        tx.send(InputData::DeviceConnected(radio.clone())).unwrap();
+        tx.send(InputData::SwitchInputData(switch.clone(), SwitchPanelInputs::new().with_taxi_lights(true))).unwrap();
+
+        assert_eq!(radios.next().await, Some(InputData::DeviceConnected(radio)));
+        assert_eq!(switches.next().await, Some(InputEvent { device: switch, event: Event::SwitchToggled { switch: Switch::TaxiLights, on: true } }));
+
+        dispatcher.stop();
+        assert_eq!(switches.next().await, None);
+        assert_eq!(radios.next().await, None);
+    }
+
+    #[tokio::test]
+    async fn sink() {
+        let (tx, rx) = mpsc::channel();
+        let sink = PanelSink::new(tx);
+        sink.send(RadioPanelCommands::SetUpperActiveFrequency(118.0)).await.unwrap();
+        assert_eq!(rx.try_recv(), Ok(RadioPanelCommands::SetUpperActiveFrequency(118.0)));
+        drop(rx);
+        assert_eq!(sink.send(RadioPanelCommands::SetUpperActiveFrequency(118.0)).await, Err(Error::ChannelClosed));
+    }
+}
@@ -0,0 +1,358 @@
+use std::env;
+use std::io::{self, Write};
+use std::process;
+use std::time::{Duration, Instant};
+use crossterm::{cursor, event, execute, queue, style, terminal};
+use crossterm::event::{Event, KeyCode, KeyEventKind, KeyModifiers};
+use crossterm::style::{Color, Stylize};
+use flightpanels_rs::prelude::*;
+use flightpanels_rs::simulator::{self, Simulator, VirtualPanel};
+
+/// How long a button stays pressed after its key.
+const PRESS_TIME: Duration = Duration::from_millis(150);
+/// How often the screen is refreshed when no key is pressed.
+const REFRESH_INTERVAL: Duration = Duration::from_millis(50);
+
+const USAGE: &str = "\
+usage: flightpanels-sim [address] [--panels multi,radio,switch,fip]
+
+Simulates panels for applications started with FLIGHTPANELS_SIMULATOR=<address>,
+which have to be built with the simulator feature. The address defaults to 127.0.0.1:7979, all four panels are simulated by default.";
+
+/// What a key does to the input bits of a panel.
+enum Action {
+    /// Flips a switch.
+    Toggle(u32),
+    /// Holds a button down for `PRESS_TIME`.
+    Press(u32),
+    /// Turns a knob by one detent.
+    Detent(u32),
+    /// Moves a selector or lever to its next position.
+    Cycle(Vec<(u32, &'static str)>)
+}
+
+struct Control {
+    key: char,
+    label: &'static str,
+    action: Action
+}
+
+fn control(key: char, label: &'static str, action: Action) -> Control {
+    Control { key, label, action }
+}
+
+fn radio_controls() -> Vec<Control> {
+    let bits = |set: fn(RadioPanelInputs) -> RadioPanelInputs| u32::from(set(RadioPanelInputs::new()));
+    let selector = |shift: u32| ["COM1", "COM2", "NAV1", "NAV2", "ADF", "DME", "XPDR"].iter().enumerate()
+        .map(|(bit, name)| (1u32 << (bit as u32 + shift), *name))
+        .collect();
+    vec![
+        control('z', "upper selector", Action::Cycle(selector(0))),
+        control('q', "upper swap", Action::Press(bits(|inputs| inputs.with_swap1(true)))),
+        control('w', "upper fine -", Action::Detent(bits(|inputs| inputs.with_fine_dec1(true)))),
+        control('e', "upper fine +", Action::Detent(bits(|inputs| inputs.with_fine_inc1(true)))),
+        control('r', "upper coarse -", Action::Detent(bits(|inputs| inputs.with_coarse_dec1(true)))),
+        control('t', "upper coarse +", Action::Detent(bits(|inputs| inputs.with_coarse_inc1(true)))),
+        control('x', "lower selector", Action::Cycle(selector(7))),
+        control('a', "lower swap", Action::Press(bits(|inputs| inputs.with_swap2(true)))),
+        control('s', "lower fine -", Action::Detent(bits(|inputs| inputs.with_fine_dec2(true)))),
+        control('d', "lower fine +", Action::Detent(bits(|inputs| inputs.with_fine_inc2(true)))),
+        control('f', "lower coarse -", Action::Detent(bits(|inputs| inputs.with_coarse_dec2(true)))),
+        control('g', "lower coarse +", Action::Detent(bits(|inputs| inputs.with_coarse_inc2(true))))
+    ]
+}
+
+fn multi_controls() -> Vec<Control> {
+    let bits = |set: fn(MultiPanelInputs) -> MultiPanelInputs| u32::from(set(MultiPanelInputs::new()));
+    vec![
+        control('z', "selector", Action::Cycle(["ALT", "VS", "IAS", "HDG", "CRS"].iter().enumerate().map(|(bit, name)| (1u32 << bit, *name)).collect())),
+        control('q', "AP", Action::Press(bits(|inputs| inputs.with_ap(true)))),
+        control('w', "HDG", Action::Press(bits(|inputs| inputs.with_hdg(true)))),
+        control('e', "NAV", Action::Press(bits(|inputs| inputs.with_nav(true)))),
+        control('r', "IAS", Action::Press(bits(|inputs| inputs.with_ias(true)))),
+        control('t', "ALT", Action::Press(bits(|inputs| inputs.with_alt(true)))),
+        control('y', "VS", Action::Press(bits(|inputs| inputs.with_vs(true)))),
+        control('u', "APR", Action::Press(bits(|inputs| inputs.with_apr(true)))),
+        control('i', "REV", Action::Press(bits(|inputs| inputs.with_rev(true)))),
+        control('a', "auto throttle", Action::Toggle(bits(|inputs| inputs.with_auto_throttle(true)))),
+        control('s', "flaps", Action::Cycle(vec![
+            (bits(|inputs| inputs.with_flaps_up(true)), "up"),
+            (0, "neutral"),
+            (bits(|inputs| inputs.with_flaps_down(true)), "down")
+        ])),
+        control('d', "pitch trim down", Action::Detent(bits(|inputs| inputs.with_pitch_down(true)))),
+        control('f', "pitch trim up", Action::Detent(bits(|inputs| inputs.with_pitch_up(true)))),
+        control('j', "jog -", Action::Detent(bits(|inputs| inputs.with_jog_dec(true)))),
+        control('k', "jog +", Action::Detent(bits(|inputs| inputs.with_jog_inc(true))))
+    ]
+}
+
+fn switch_controls() -> Vec<Control> {
+    let bits = |set: fn(SwitchPanelInputs) -> SwitchPanelInputs| u32::from(set(SwitchPanelInputs::new()));
+    vec![
+        control('q', "battery", Action::Toggle(bits(|inputs| inputs.with_battery(true)))),
+        control('w', "alternator", Action::Toggle(bits(|inputs| inputs.with_alt(true)))),
+        control('e', "avionics", Action::Toggle(bits(|inputs| inputs.with_avionics(true)))),
+        control('r', "fuel pump", Action::Toggle(bits(|inputs| inputs.with_fuel_pump(true)))),
+        control('t', "de-ice", Action::Toggle(bits(|inputs| inputs.with_de_ice(true)))),
+        control('y', "pitot heat", Action::Toggle(bits(|inputs| inputs.with_pitot_heat(true)))),
+        control('u', "cowl", Action::Toggle(bits(|inputs| inputs.with_cowl(true)))),
+        control('i', "panel lights", Action::Toggle(bits(|inputs| inputs.with_panel_lights(true)))),
+        control('o', "beacon", Action::Toggle(bits(|inputs| inputs.with_beacon_lights(true)))),
+        control('p', "nav lights", Action::Toggle(bits(|inputs| inputs.with_navigation_lights(true)))),
+        control('a', "strobe", Action::Toggle(bits(|inputs| inputs.with_strobe_lights(true)))),
+        control('s', "taxi", Action::Toggle(bits(|inputs| inputs.with_taxi_lights(true)))),
+        control('d', "landing", Action::Toggle(bits(|inputs| inputs.with_landing_lights(true)))),
+        control('z', "engine", Action::Cycle(vec![
+            (bits(|inputs| inputs.with_engine_selector(EngineSelection::OFF)), "OFF"),
+            (bits(|inputs| inputs.with_engine_selector(EngineSelection::RIGHT)), "R"),
+            (bits(|inputs| inputs.with_engine_selector(EngineSelection::LEFT)), "L"),
+            (bits(|inputs| inputs.with_engine_selector(EngineSelection::BOTH)), "BOTH"),
+            (bits(|inputs| inputs.with_engine_selector(EngineSelection::START)), "START")
+        ])),
+        control('g', "gear", Action::Cycle(vec![
+            (bits(|inputs| inputs.with_gear_down(true)), "down"),
+            (bits(|inputs| inputs.with_gear_up(true)), "up")
+        ]))
+    ]
+}
+
+fn fip_controls() -> Vec<Control> {
+    let bits = |set: fn(FlightInstrumentPanelInputs) -> FlightInstrumentPanelInputs| u32::from(u16::from(set(FlightInstrumentPanelInputs::new())));
+    vec![
+        control('q', "S1", Action::Press(bits(|inputs| inputs.with_s1(true)))),
+        control('w', "S2", Action::Press(bits(|inputs| inputs.with_s2(true)))),
+        control('e', "S3", Action::Press(bits(|inputs| inputs.with_s3(true)))),
+        control('r', "S4", Action::Press(bits(|inputs| inputs.with_s4(true)))),
+        control('t', "S5", Action::Press(bits(|inputs| inputs.with_s5(true)))),
+        control('y', "S6", Action::Press(bits(|inputs| inputs.with_s6(true)))),
+        control('u', "up", Action::Press(bits(|inputs| inputs.with_up(true)))),
+        control('i', "down", Action::Press(bits(|inputs| inputs.with_down(true)))),
+        control('a', "left knob -", Action::Detent(bits(|inputs| inputs.with_left_encoder_dec(true)))),
+        control('s', "left knob +", Action::Detent(bits(|inputs| inputs.with_left_encoder_inc(true)))),
+        control('d', "right knob -", Action::Detent(bits(|inputs| inputs.with_right_encoder_dec(true)))),
+        control('f', "right knob +", Action::Detent(bits(|inputs| inputs.with_right_encoder_inc(true))))
+    ]
+}
+
+fn controls(kind: PanelKind) -> Vec<Control> {
+    match kind {
+        PanelKind::Radio => radio_controls(),
+        PanelKind::Multi => multi_controls(),
+        PanelKind::Switch => switch_controls(),
+        PanelKind::FlightInstrument => fip_controls()
+    }
+}
+
+/// Position of a `Cycle` control, `None` if the inputs match none of them.
+fn position(states: &[(u32, &'static str)], inputs: u32) -> Option<usize> {
+    let mask = states.iter().fold(0, |mask, (bits, _)| mask | bits);
+    states.iter().position(|(bits, _)| inputs & mask == *bits)
+}
+
+fn state(control: &Control, inputs: u32) -> String {
+    match &control.action {
+        Action::Toggle(bits) => if inputs & bits != 0 { "on".to_string() } else { "off".to_string() },
+        Action::Press(bits) => if inputs & bits != 0 { "pressed".to_string() } else { String::new() },
+        Action::Detent(_) => String::new(),
+        Action::Cycle(states) => position(states, inputs).map_or("-", |index| states[index].1).to_string()
+    }
+}
+
+struct App {
+    simulator: Simulator,
+    controls: Vec<Vec<Control>>,
+    focus: usize,
+    /// Buttons to release, by panel.
+    releases: Vec<(Instant, usize, u32)>
+}
+
+impl App {
+    fn key(&mut self, key: char) {
+        let Some(control) = self.controls[self.focus].iter().find(|control| control.key == key) else { return };
+        let index = self.focus;
+        match &control.action {
+            Action::Toggle(bits) => self.simulator.update(index, |inputs| inputs ^ bits),
+            Action::Press(bits) => {
+                self.simulator.update(index, |inputs| inputs | bits);
+                self.releases.push((Instant::now() + PRESS_TIME, index, *bits));
+            },
+            Action::Detent(bits) => {
+                // every report with the bit set is a detent, so clear it in the next one
+                self.simulator.update(index, |inputs| inputs | bits);
+                self.simulator.update(index, |inputs| inputs & !bits);
+            },
+            Action::Cycle(states) => {
+                let mask = states.iter().fold(0, |mask, (bits, _)| mask | bits);
+                self.simulator.update(index, |inputs| {
+                    let next = position(states, inputs).map_or(0, |position| (position + 1) % states.len());
+                    inputs & !mask | states[next].0
+                });
+            }
+        }
+    }
+
+    fn release(&mut self) {
+        let now = Instant::now();
+        for (_, index, bits) in self.releases.iter().filter(|(due, _, _)| *due <= now) {
+            self.simulator.update(*index, |inputs| inputs & !bits);
+        }
+        self.releases.retain(|(due, _, _)| *due > now);
+    }
+
+    fn draw(&self, out: &mut impl Write) -> io::Result<()> {
+        let mut lines: Vec<String> = vec![
+            format!("flightpanels simulator, run applications with {}={}", simulator::ENV, self.simulator.address()),
+            "Tab: next panel, Esc: quit".to_string(),
+            String::new()
+        ];
+        for (index, panel) in self.simulator.panels().iter().enumerate() {
+            let marker = if index == self.focus { ">" } else { " " };
+            let status = if panel.connected { "connected".green().to_string() } else { "waiting".dark_grey().to_string() };
+            lines.push(format!("{} {} {} ({})", marker, panel.kind, panel.serial, status));
+            lines.extend(outputs(panel).into_iter().map(|line| format!("    {}", line)));
+            if index == self.focus {
+                let mut line = String::from("   ");
+                for control in &self.controls[index] {
+                    let state = state(control, panel.inputs);
+                    let entry = if state.is_empty() { format!(" [{}] {}", control.key, control.label) } else { format!(" [{}] {}: {}", control.key, control.label, state) };
+                    if line.len() + entry.len() > 100 {
+                        lines.push(std::mem::replace(&mut line, String::from("   ")));
+                    }
+                    line.push_str(&entry);
+                }
+                lines.push(line);
+            }
+            lines.push(String::new());
+        }
+        queue!(out, cursor::MoveTo(0, 0))?;
+        for line in lines {
+            queue!(out, terminal::Clear(terminal::ClearType::CurrentLine), style::Print(line), cursor::MoveToNextLine(1))?;
+        }
+        queue!(out, terminal::Clear(terminal::ClearType::FromCursorDown))?;
+        out.flush()
+    }
+}
+
+fn led(name: &str, on: bool) -> String {
+    if on { format!("{}", name.black().on_yellow()) } else { name.dark_grey().to_string() }
+}
+
+fn gear_led(name: &str, green: bool, red: bool) -> String {
+    let color = match (green, red) {
+        (true, true) => Color::Yellow,
+        (true, false) => Color::Green,
+        (false, true) => Color::Red,
+        (false, false) => Color::DarkGrey
+    };
+    format!("{}", name.with(color))
+}
+
+/// What the application shows on a panel.
+fn outputs(panel: &VirtualPanel) -> Vec<String> {
+    let display = |text: &str| format!("{}", format!("[{:>6}]", text).red().on_black());
+    match panel.kind {
+        PanelKind::Radio => {
+            let inputs = RadioPanelInputs::from(panel.inputs);
+            let [upper_active, upper_standby, lower_active, lower_standby] = panel.radio_displays();
+            vec![
+                format!("{:<5} {} {}", format!("{:?}", inputs.selector1()), display(&upper_active), display(&upper_standby)),
+                format!("{:<5} {} {}", format!("{:?}", inputs.selector2()), display(&lower_active), display(&lower_standby))
+            ]
+        },
+        PanelKind::Multi => {
+            let [upper, lower] = panel.multi_displays();
+            let leds = panel.multi_leds();
+            vec![
+                format!("{} {}", display(&upper), display(&lower)),
+                [("AP", leds.ap()), ("HDG", leds.hdg()), ("NAV", leds.nav()), ("IAS", leds.ias()), ("ALT", leds.alt()), ("VS", leds.vs()), ("APR", leds.apr()), ("REV", leds.rev())]
+                    .iter().map(|(name, on)| led(name, *on)).collect::<Vec<String>>().join(" ")
+            ]
+        },
+        PanelKind::Switch => {
+            let leds = GearLedsStates::from_bits_truncate(panel.gear_leds());
+            vec![format!("gear {} {} {}",
+                gear_led("left", leds.contains(GearLedsStates::LEFT_GREEN), leds.contains(GearLedsStates::LEFT_RED)),
+                gear_led("nose", leds.contains(GearLedsStates::UP_GREEN), leds.contains(GearLedsStates::UP_RED)),
+                gear_led("right", leds.contains(GearLedsStates::RIGHT_GREEN), leds.contains(GearLedsStates::RIGHT_RED)))]
+        },
+        PanelKind::FlightInstrument => {
+            let names = ["", "S1", "S2", "S3", "S4", "S5", "S6", "up", "down"];
+            let leds: Vec<String> = names.iter().zip(panel.fip_leds).skip(1).map(|(name, on)| led(name, on)).collect();
+            let image = if panel.fip_images.contains(&panel.fip_page) { "image shown" } else { "no image" };
+            vec![leds.join(" "), format!("page {}, {}", panel.fip_page, image)]
+        }
+    }
+}
+
+fn run(address: &str, kinds: &[PanelKind]) -> io::Result<()> {
+    let serials: Vec<String> = kinds.iter().map(|kind| format!("SIM-{}", kind.name().to_uppercase())).collect();
+    let panels: Vec<(PanelKind, &str)> = kinds.iter().zip(&serials).map(|(kind, serial)| (*kind, serial.as_str())).collect();
+    let simulator = Simulator::start(address, &panels).map_err(io::Error::other)?;
+    let mut app = App { simulator, controls: kinds.iter().map(|kind| controls(*kind)).collect(), focus: 0, releases: Vec::new() };
+    // real selectors and levers are always in some position
+    for (index, controls) in app.controls.iter().enumerate() {
+        for control in controls {
+            if let Action::Cycle(states) = &control.action {
+                app.simulator.update(index, |inputs| if position(states, inputs).is_none() { inputs | states[0].0 } else { inputs });
+            }
+        }
+    }
+
+    let mut out = io::stdout();
+    terminal::enable_raw_mode()?;
+    execute!(out, terminal::EnterAlternateScreen, cursor::Hide)?;
+    let result = (|| -> io::Result<()> {
+        loop {
+            app.release();
+            app.draw(&mut out)?;
+            if !event::poll(REFRESH_INTERVAL)? {
+                continue;
+            }
+            let Event::Key(key) = event::read()? else { continue };
+            if key.kind == KeyEventKind::Release {
+                continue;
+            }
+            match key.code {
+                KeyCode::Esc => return Ok(()),
+                KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => return Ok(()),
+                KeyCode::Tab => app.focus = (app.focus + 1) % app.controls.len(),
+                KeyCode::BackTab => app.focus = (app.focus + app.controls.len() - 1) % app.controls.len(),
+                KeyCode::Char(key) => app.key(key),
+                _ => ()
+            }
+        }
+    })();
+    execute!(out, cursor::Show, terminal::LeaveAlternateScreen)?;
+    terminal::disable_raw_mode()?;
+    result
+}
+
+fn main() {
+    let mut address = simulator::DEFAULT_ADDRESS.to_string();
+    let mut kinds = PanelKind::ALL.to_vec();
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--panels" => {
+                let names = args.next().unwrap_or_default();
+                match names.split(',').map(PanelKind::from_name).collect::<Option<Vec<PanelKind>>>() {
+                    Some(list) if !list.is_empty() => kinds = list,
+                    _ => {
+                        eprintln!("flightpanels-sim: invalid panels {}\n{}", names, USAGE);
+                        process::exit(1);
+                    }
+                }
+            },
+            "-h" | "--help" => {
+                println!("{}", USAGE);
+                return;
+            },
+            _ => address = arg
+        }
+    }
+    if let Err(error) = run(&address, &kinds) {
+        eprintln!("flightpanels-sim: {}", error);
+        process::exit(1);
+    }
+}
@@ -0,0 +1,216 @@
+use std::env;
+use std::process;
+use std::time::{Duration, Instant};
+use flightpanels_rs::prelude::*;
+
+/// How long to wait for a panel to be opened before giving up.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+const USAGE: &str = "\
+usage: flightpanels <command> [--device <name or serial>]
+
+commands:
+  list                                   list connected panels
+  monitor [--raw]                        print input events until interrupted, --raw prints whole reports
+  set-radio <display> <frequency>        display: upper-active, upper-standby, lower-active, lower-standby
+  set-multi <display> <value>            display: upper, lower
+  gear-leds <up>,<left>,<right>          colors: off, green, yellow, red
+  blank                                  switch every LED off and blank every display
+
+set-radio, set-multi and gear-leds start from a dark panel: the panels only take complete
+reports, so every other display and LED of the panel addressed is blanked or switched off.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    if let Err(message) = run(args) {
+        eprintln!("flightpanels: {}", message);
+        process::exit(1);
+    }
+}
+
+fn run(mut args: Vec<String>) -> Result<(), String> {
+    let device = match args.iter().position(|arg| arg == "--device") {
+        Some(index) if index + 1 < args.len() => Some(args.drain(index..index + 2).nth(1).unwrap_or_default()),
+        Some(_) => return Err("--device needs a name or serial".to_string()),
+        None => None
+    };
+    let raw = match args.iter().position(|arg| arg == "--raw") {
+        Some(index) => { args.remove(index); true },
+        None => false
+    };
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args[..] {
+        ["list"] => list(),
+        ["monitor"] => monitor(raw),
+        ["set-radio", display, frequency] => set_radio(device.as_deref(), display, frequency),
+        ["set-multi", display, value] => set_multi(device.as_deref(), display, value),
+        ["gear-leds", colors] => gear_leds(device.as_deref(), colors),
+        ["blank"] => blank(),
+        ["help"] | ["--help"] | ["-h"] => { println!("{}", USAGE); Ok(()) },
+        _ => Err(format!("invalid arguments\n{}", USAGE))
+    }
+}
+
+fn open() -> Result<Flightpanels, String> {
+    Flightpanels::new().map_err(|error| format!("could not initialize hidapi: {}", error))
+}
+
+fn list() -> Result<(), String> {
+    let panels = discover().map_err(|error| error.to_string())?;
+    if panels.is_empty() {
+        println!("no panels connected");
+    }
+    for panel in panels {
+        println!("{:<24} {:<12} release {:04x} {:<14} {}",
+            panel.kind.to_string(),
+            panel.serial.as_deref().unwrap_or("-"),
+            panel.release_number,
+            if panel.accessible { "ok" } else { "no permission" },
+            panel.path);
+    }
+    Ok(())
+}
+
+fn monitor(raw: bool) -> Result<(), String> {
+    let panels = open()?;
+    let mut decoder = EventDecoder::new();
+    println!("waiting for inputs, press Ctrl-C to stop");
+    for input in panels.inputs().iter() {
+        match &input {
+            InputData::DeviceConnected(device) => println!("{} connected", device),
+            InputData::DeviceDisconnected(device) => println!("{} disconnected", device),
+            InputData::DeviceError(device, error) => println!("{}: {}", device, error),
+            _ if raw => println!("{:#?}", input),
+            _ => ()
+        }
+        for event in decoder.decode(&input) {
+            println!("{}: {:?}", event.device, event.event);
+        }
+    }
+    Ok(())
+}
+
+/// Waits until a panel of `kind` is driven, so commands sent afterwards reach it before shutdown.
+fn wait_connected(panels: &Flightpanels, kind: PanelKind, device: Option<&str>) -> Result<DeviceId, String> {
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    let matches = |id: &DeviceId| id.kind == kind && device.is_none_or(|device| id.serial == device || id.name.as_deref() == Some(device));
+    while let Some(timeout) = deadline.checked_duration_since(Instant::now()) {
+        match panels.inputs().recv_timeout(timeout) {
+            Ok(InputData::DeviceConnected(id)) if matches(&id) => return Ok(id),
+            Ok(InputData::DeviceError(id, error)) if id.kind == kind => return Err(format!("{}: {}", id, error)),
+            Ok(_) => (),
+            Err(_) => break
+        }
+    }
+    match device {
+        Some(device) => Err(format!("{} {} not connected", kind, device)),
+        None => Err(format!("no {} connected", kind))
+    }
+}
+
+fn set_radio(device: Option<&str>, display: &str, frequency: &str) -> Result<(), String> {
+    let display = match display {
+        "upper-active" => RadioDisplay::UpperActive,
+        "upper-standby" => RadioDisplay::UpperStandby,
+        "lower-active" => RadioDisplay::LowerActive,
+        "lower-standby" => RadioDisplay::LowerStandby,
+        _ => return Err(format!("unknown radio display {}", display))
+    };
+    let frequency: f32 = frequency.parse().map_err(|_| format!("invalid frequency {}", frequency))?;
+    // check the value here, the panel thread would only report it on the input channel
+    RadioPanelOutputs::default().set_display(display, frequency).map_err(|error| error.to_string())?;
+    let command = match display {
+        RadioDisplay::UpperActive => RadioPanelCommands::SetUpperActiveFrequency(frequency),
+        RadioDisplay::UpperStandby => RadioPanelCommands::SetUpperStandbyFrequency(frequency),
+        RadioDisplay::LowerActive => RadioPanelCommands::SetLowerActiveFrequency(frequency),
+        RadioDisplay::LowerStandby => RadioPanelCommands::SetLowerStandbyFrequency(frequency)
+    };
+    let panels = open()?;
+    let sender = match device {
+        Some(device) => panels.radio_panel_for(device),
+        None => panels.radio_panel().clone()
+    };
+    let id = wait_connected(&panels, PanelKind::Radio, device)?;
+    sender.send(command).map_err(|error| error.to_string())?;
+    println!("{}: {} set to {}", id, display.name(), frequency);
+    Ok(())
+}
+
+fn set_multi(device: Option<&str>, display: &str, value: &str) -> Result<(), String> {
+    let display = match display {
+        "upper" => MultiDisplay::UpperDisplay,
+        "lower" => MultiDisplay::LowerDisplay,
+        _ => return Err(format!("unknown multi panel display {}", display))
+    };
+    let value: i32 = value.parse().map_err(|_| format!("invalid value {}", value))?;
+    MultiPanelOutputs::default().set_display(display, value).map_err(|error| error.to_string())?;
+    let command = match display {
+        MultiDisplay::UpperDisplay => MultiPanelCommands::SetUpperDisplay(value),
+        MultiDisplay::LowerDisplay => MultiPanelCommands::SetLowerDisplay(value)
+    };
+    let panels = open()?;
+    let sender = match device {
+        Some(device) => panels.multi_panel_for(device),
+        None => panels.multi_panel().clone()
+    };
+    let id = wait_connected(&panels, PanelKind::Multi, device)?;
+    sender.send(command).map_err(|error| error.to_string())?;
+    println!("{}: {} display set to {}", id, display.name(), value);
+    Ok(())
+}
+
+fn color(name: &str) -> Result<LedColors, String> {
+    match name.trim() {
+        "off" => Ok(LedColors::Off),
+        "green" => Ok(LedColors::Green),
+        "yellow" => Ok(LedColors::Yellow),
+        "red" => Ok(LedColors::Red),
+        _ => Err(format!("unknown color {}", name))
+    }
+}
+
+fn gear_leds(device: Option<&str>, colors: &str) -> Result<(), String> {
+    let colors = colors.split(',').map(color).collect::<Result<Vec<LedColors>, String>>()?;
+    let [up, left, right] = colors[..] else {
+        return Err("expected three colors: up,left,right".to_string());
+    };
+    let panels = open()?;
+    let sender = match device {
+        Some(device) => panels.switch_panel_for(device),
+        None => panels.switch_panel().clone()
+    };
+    let id = wait_connected(&panels, PanelKind::Switch, device)?;
+    for command in [SwitchPanelCommands::SetUpLedTo(up), SwitchPanelCommands::SetLeftLedTo(left), SwitchPanelCommands::SetRightLedTo(right)] {
+        sender.send(command).map_err(|error| error.to_string())?;
+    }
+    println!("{}: gear LEDs set to {:?}, {:?}, {:?}", id, up, left, right);
+    Ok(())
+}
+
+fn blank() -> Result<(), String> {
+    let panels = open()?;
+    let mut pending = panels.devices();
+    if pending.is_empty() {
+        return Err("no panels connected".to_string());
+    }
+    panels.set_blank_on_shutdown(true);
+    // every panel has to be driven by its thread, or there is nobody to blank it
+    let deadline = Instant::now() + CONNECT_TIMEOUT;
+    while !pending.is_empty() {
+        let Some(timeout) = deadline.checked_duration_since(Instant::now()) else { break };
+        match panels.inputs().recv_timeout(timeout) {
+            Ok(InputData::DeviceConnected(id)) => pending.retain(|device| *device != id),
+            Ok(_) => (),
+            Err(_) => break
+        }
+    }
+    let devices = panels.devices();
+    panels.shutdown();
+    for device in devices.iter().filter(|device| !pending.contains(device)) {
+        println!("{} blanked", device);
+    }
+    for device in pending {
+        println!("{} not blanked, it was not ready in time", device);
+    }
+    Ok(())
+}
@@ -0,0 +1,718 @@
+use hidapi::{DeviceInfo, HidApi};
+use std::any::Any;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::{DeviceId, InputData, PanelKind};
+use crate::error::{Error, Result};
+use crate::recording::{Recorded, Recorder};
+use crate::transport::{HidTransport, Transport};
+
+/// How often the monitor looks for panels that have been plugged in.
+const SCAN_INTERVAL: Duration = Duration::from_millis(1000);
+/// How long a read blocks (ms) before the reader looks whether the panel has been stopped.
+const READ_TIMEOUT: i32 = 50;
+/// How long a thread waiting for commands or devices blocks before it looks whether to stop.
+const IDLE_INTERVAL: Duration = Duration::from_millis(50);
+
+/// Output state and report encoding of one panel model.
+///
+/// The state outlives the device, so it can be replayed when a panel comes back.
+pub(crate) trait Panel: Default + Send + 'static {
+    type Command: Send + 'static;
+    const KIND: PanelKind;
+    const ID: (u16, u16);
+
+    fn decode(device: &DeviceId, report: [u8; 4]) -> InputData;
+    /// Updates the output state, nothing is sent before the next `flush`.
+    fn apply(&mut self, command: Self::Command) -> Result<()>;
+    /// Sends everything that changed since the last flush.
+    fn flush(&mut self, device: &dyn Transport) -> Result<()>;
+    /// Forgets what the device shows, the next flush sends the complete state.
+    fn invalidate(&mut self);
+    /// Switches every LED off and blanks every display, sent with the next flush.
+    fn dark(&mut self);
+
+    fn open(api: &HidApi, info: &DeviceInfo) -> Result<Box<dyn Transport>> {
+        let device = HidTransport::open(api, info.path()).map_err(|error| crate::error::open_error(api, Self::KIND, Self::ID, error))?;
+        Ok(Box::new(device))
+    }
+}
+
+/// Stop request, latest inputs and recorder, shared by the threads of a panel or of all panels.
+pub(crate) struct Control {
+    running: AtomicBool,
+    blank: AtomicBool,
+    states: Mutex<HashMap<DeviceId, InputData>>,
+    recorder: Mutex<Option<Arc<Recorder>>>
+}
+
+impl Control {
+    pub(crate) fn new() -> Arc<Self> {
+        Arc::new(Control { running: AtomicBool::new(true), blank: AtomicBool::new(false), states: Mutex::new(HashMap::new()), recorder: Mutex::new(None) })
+    }
+
+    fn states(&self) -> MutexGuard<'_, HashMap<DeviceId, InputData>> {
+        self.states.lock().expect("states poisoned")
+    }
+
+    /// Latest inputs of a connected panel.
+    pub(crate) fn state(&self, device: &DeviceId) -> Option<InputData> {
+        self.states().get(device).cloned()
+    }
+
+    /// Latest inputs of all connected panels.
+    pub(crate) fn all_states(&self) -> Vec<InputData> {
+        self.states().values().cloned().collect()
+    }
+
+    pub(crate) fn running(&self) -> bool {
+        self.running.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn stop(&self) {
+        self.running.store(false, Ordering::Relaxed);
+    }
+
+    /// Whether panels go dark when they are stopped.
+    pub(crate) fn set_blank(&self, blank: bool) {
+        self.blank.store(blank, Ordering::Relaxed);
+    }
+
+    fn blank(&self) -> bool {
+        self.blank.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn set_recorder(&self, recorder: Option<Recorder>) {
+        *self.recorder.lock().expect("recorder poisoned") = recorder.map(Arc::new);
+    }
+
+    pub(crate) fn recorder(&self) -> Option<Arc<Recorder>> {
+        self.recorder.lock().expect("recorder poisoned").clone()
+    }
+}
+
+/// Thread of a panel started with `receive` or `receive_from`.
+///
+/// `receive` drives the first panel of its kind found, without reconnecting. `receive_from` runs
+/// the panel on any transport, e.g. a [`MockTransport`](crate::transport::MockTransport), and its
+/// events carry a device id with an empty serial.
+///
+/// Dropping the handle leaves the thread running, like dropping a `JoinHandle`.
+pub struct PanelHandle {
+    control: Arc<Control>,
+    thread: JoinHandle<()>
+}
+
+impl PanelHandle {
+    /// Stops the thread and waits for it, with `blank` the panel is switched dark first.
+    pub fn stop(self, blank: bool) {
+        self.control.set_blank(blank);
+        self.control.stop();
+        self.join();
+    }
+
+    /// Waits until the thread ends by itself, after a device error or when nobody listens anymore.
+    pub fn join(self) {
+        let _ = self.thread.join();
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    /// Latest inputs of the panel, `None` before the first report and after it failed.
+    pub fn current_state(&self) -> Option<InputData> {
+        self.control.all_states().pop()
+    }
+
+    /// Writes all traffic of the panel to `recorder` from now on, `None` stops recording.
+    pub fn record(&self, recorder: Option<Recorder>) {
+        self.control.set_recorder(recorder);
+    }
+}
+
+/// Serial number of a panel, its path for panels that do not report one.
+pub(crate) fn serial(info: &DeviceInfo) -> String {
+    match info.serial_number() {
+        Some(serial) if !serial.is_empty() => serial.to_string(),
+        _ => info.path().to_string_lossy().into_owned()
+    }
+}
+
+/// Opens the first panel of model `P`.
+pub(crate) fn open_first<P: Panel>(api: &HidApi) -> Result<(DeviceId, Box<dyn Transport>)> {
+    let info = api.device_list()
+        .find(|info| (info.vendor_id(), info.product_id()) == P::ID)
+        .ok_or(Error::DeviceNotFound(P::KIND))?;
+    Ok((DeviceId::new(P::KIND, serial(info)), P::open(api, info)?))
+}
+
+/*
+Threads of a connected panel
+
+reader: blocks reading input reports and passes them on, ends the connection when a read fails
+or the panel is stopped
+caller of `run`: blocks on the command channel and passes the commands on
+panel thread: waits for both with a single recv, applies everything queued when it wakes up and
+sends the changes as one update, so commands neither wait for a read timeout nor go out one by one
+*/
+
+/// What the panel thread waits for.
+enum Message<C> {
+    /// Commands queued together.
+    Commands(Vec<C>),
+    Input([u8; 4]),
+    /// The read failed, usually because the panel was unplugged.
+    Failed(Error),
+    Stopped
+}
+
+/// How a connection ended without a device error.
+enum Exit {
+    Stopped,
+    /// Nobody listens to the inputs anymore.
+    Unheard
+}
+
+/// Runs the I/O loop until the device fails (`Err`), nobody listens anymore or it is stopped (`Ok`).
+pub(crate) fn run<P: Panel>(panel: &mut P, id: &DeviceId, device: &dyn Transport, tx: &Sender<InputData>, rx: &Receiver<P::Command>, control: &Control) -> Result<()> {
+    let result = run_connected(panel, id, device, tx, rx, control);
+    control.states().remove(id);
+    result
+}
+
+fn run_connected<P: Panel>(panel: &mut P, id: &DeviceId, device: &dyn Transport, tx: &Sender<InputData>, rx: &Receiver<P::Command>, control: &Control) -> Result<()> {
+    if let Err(error) = panel.flush(device) {
+        let _ = tx.send(InputData::DeviceError(id.clone(), error));
+    }
+    // ask for the positions of the switches right away instead of waiting for the first change,
+    // not every panel and platform supports it, those simply report on the first change
+    let mut report = [0u8; 5];
+    if let Ok(size) = device.get_input_report(&mut report) {
+        if size > 1 && !input(id, [report[1], report[2], report[3], report[4]], tx, control, P::decode) {
+            return Ok(());
+        }
+    }
+    let (messages_tx, messages) = mpsc::channel();
+    let done = &AtomicBool::new(false);
+    let serving = &mut *panel;
+    let (result, messages) = thread::scope(|scope| {
+        let reader_tx = messages_tx.clone();
+        scope.spawn(move || read_reports(device, reader_tx, done, control));
+        let panel_thread = scope.spawn(move || {
+            let result = serve(serving, id, device, tx, &messages, control);
+            done.store(true, Ordering::Relaxed);
+            (result, messages)
+        });
+        forward_commands(rx, &messages_tx, done);
+        panel_thread.join().unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    });
+    // commands passed on while the connection ended are kept for the next one
+    for message in messages.try_iter() {
+        if let Message::Commands(commands) = message {
+            for command in commands {
+                let _ = panel.apply(command);
+            }
+        }
+    }
+    match result? {
+        Exit::Unheard => Ok(()),
+        Exit::Stopped => {
+            // commands sent right before stopping are still shown, unless the panel goes dark anyway
+            for command in rx.try_iter() {
+                let _ = panel.apply(command);
+            }
+            if control.blank() {
+                panel.dark();
+            }
+            // the panel was not lost, so a failing last update is an error rather than a disconnect
+            if let Err(error) = panel.flush(device) {
+                let _ = tx.send(InputData::DeviceError(id.clone(), error));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Applies commands and passes on inputs until the connection ends.
+fn serve<P: Panel>(panel: &mut P, id: &DeviceId, device: &dyn Transport, tx: &Sender<InputData>, messages: &Receiver<Message<P::Command>>, control: &Control) -> Result<Exit> {
+    let mut changed = false;
+    loop {
+        let message = match messages.try_recv() {
+            Ok(message) => message,
+            Err(_) => {
+                // nothing queued anymore, the changes so far go out together
+                if changed {
+                    if let Err(error) = panel.flush(device) {
+                        let _ = tx.send(InputData::DeviceError(id.clone(), error));
+                    }
+                    changed = false;
+                }
+                match messages.recv() {
+                    Ok(message) => message,
+                    Err(_) => return Ok(Exit::Stopped)
+                }
+            }
+        };
+        match message {
+            Message::Commands(commands) => {
+                for command in commands {
+                    if let Err(error) = panel.apply(command) {
+                        let _ = tx.send(InputData::DeviceError(id.clone(), error));
+                    }
+                }
+                changed = true;
+            },
+            Message::Input(report) => {
+                if !input(id, report, tx, control, P::decode) {
+                    return Ok(Exit::Unheard);
+                }
+            },
+            Message::Failed(error) => return Err(error),
+            Message::Stopped => return Ok(Exit::Stopped)
+        }
+    }
+}
+
+/// Reads input reports until the connection is done, a read fails or the panel is stopped.
+fn read_reports<C: Send>(device: &dyn Transport, messages: Sender<Message<C>>, done: &AtomicBool, control: &Control) {
+    while !done.load(Ordering::Relaxed) {
+        if !control.running() {
+            let _ = messages.send(Message::Stopped);
+            return;
+        }
+        let mut report = [0u8; 4];
+        match device.read_timeout(&mut report, READ_TIMEOUT) {
+            Ok(0) => (),
+            Ok(_) => {
+                let _ = messages.send(Message::Input(report));
+            },
+            Err(error) => {
+                let _ = messages.send(Message::Failed(error));
+                return;
+            }
+        }
+    }
+}
+
+/// Passes on commands until the connection is done, everything queued at once as one message.
+fn forward_commands<C>(rx: &Receiver<C>, messages: &Sender<Message<C>>, done: &AtomicBool) {
+    while !done.load(Ordering::Relaxed) {
+        match rx.recv_timeout(IDLE_INTERVAL) {
+            Ok(command) => {
+                let commands = std::iter::once(command).chain(rx.try_iter()).collect();
+                let _ = messages.send(Message::Commands(commands));
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            // nobody sends commands anymore, the inputs still go on
+            Err(RecvTimeoutError::Disconnected) => return
+        }
+    }
+}
+
+/// Decodes a report, keeps it as current state and passes it on, false if nobody listens anymore.
+fn input(id: &DeviceId, report: [u8; 4], tx: &Sender<InputData>, control: &Control, decode: fn(&DeviceId, [u8; 4]) -> InputData) -> bool {
+    let input = decode(id, report);
+    control.states().insert(id.clone(), input.clone());
+    tx.send(input).is_ok()
+}
+
+/// Decodes a report of any panel model.
+pub(crate) fn decode(id: &DeviceId, report: [u8; 4]) -> InputData {
+    use crate::{flight_instrument_panel, multi_panel, radio_panel, switch_panel};
+    match id.kind {
+        PanelKind::Multi => multi_panel::MultiPanel::decode(id, report),
+        PanelKind::Radio => radio_panel::RadioPanel::decode(id, report),
+        PanelKind::Switch => switch_panel::SwitchPanel::decode(id, report),
+        PanelKind::FlightInstrument => flight_instrument_panel::FlightInstrumentPanel::decode(id, report)
+    }
+}
+
+/// Runs one panel on a single device, without reconnecting.
+pub(crate) fn spawn<P: Panel, T: Transport + 'static>(id: DeviceId, device: T, tx: Sender<InputData>, rx: Receiver<P::Command>) -> PanelHandle {
+    let control = Control::new();
+    let thread_control = control.clone();
+    let thread = thread::spawn(move || {
+        let device = Recorded::new(device, id.clone(), thread_control.clone());
+        let mut panel = P::default();
+        if let Err(error) = run(&mut panel, &id, &device, &tx, &rx, &thread_control) {
+            let _ = tx.send(InputData::DeviceError(id, error));
+        }
+    });
+    PanelHandle { control, thread }
+}
+
+/// Keeps the state of one panel for the whole session and drives every device handed to it.
+pub(crate) fn worker<P: Panel>(mut id: DeviceId, tx: Sender<InputData>, rx: Receiver<P::Command>, devices: Receiver<(DeviceId, Box<dyn Transport>)>, connected: Arc<AtomicBool>, control: Arc<Control>) {
+    let mut panel = P::default();
+    while control.running() {
+        // while disconnected commands only update the state that is replayed later
+        while let Ok(command) = rx.try_recv() {
+            if let Err(error) = panel.apply(command) {
+                let _ = tx.send(InputData::DeviceError(id.clone(), error));
+            }
+        }
+        match devices.recv_timeout(IDLE_INTERVAL) {
+            Ok((device_id, device)) => {
+                id = device_id;
+                panel.invalidate();
+                if tx.send(InputData::DeviceConnected(id.clone())).is_err() {
+                    return;
+                }
+                let device = Recorded::new(device, id.clone(), control.clone());
+                let result = run(&mut panel, &id, &device, &tx, &rx, &control);
+                drop(device);
+                connected.store(false, Ordering::Relaxed);
+                let Err(error) = result else { return };
+                // what ended the connection, then the disconnect itself
+                if tx.send(InputData::DeviceError(id.clone(), error)).is_err() || tx.send(InputData::DeviceDisconnected(id.clone())).is_err() {
+                    return;
+                }
+            },
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => return
+        }
+    }
+}
+
+/// `Slot::new` for the model of a slot, to add slots for further panels of that model.
+type CreateSlot = fn(Option<String>, Option<String>, Sender<InputData>, Arc<Control>) -> Slot;
+
+/// One worker with its command channel, driving one panel at a time.
+pub(crate) struct Slot {
+    kind: PanelKind,
+    id: (u16, u16),
+    /// Serial the slot is reserved for, `None` takes any panel not claimed by another slot.
+    address: Option<String>,
+    /// Serial of the panel driven, only meaningful while `connected` is set.
+    serial: String,
+    open: fn(&HidApi, &DeviceInfo) -> Result<Box<dyn Transport>>,
+    create: CreateSlot,
+    devices: Sender<(DeviceId, Box<dyn Transport>)>,
+    // Sender<P::Command>
+    commands: Box<dyn Any + Send>,
+    connected: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>
+}
+
+impl Slot {
+    /// Starts the worker for `P` and returns the slot feeding it devices and commands.
+    pub(crate) fn new<P: Panel>(address: Option<String>, name: Option<String>, tx: Sender<InputData>, control: Arc<Control>) -> Self {
+        let (devices_tx, devices_rx) = mpsc::channel();
+        let (commands_tx, commands_rx) = mpsc::channel::<P::Command>();
+        let connected = Arc::new(AtomicBool::new(false));
+        let worker_connected = connected.clone();
+        let id = DeviceId { kind: P::KIND, serial: address.clone().unwrap_or_default(), name };
+        let thread = thread::spawn(move || worker::<P>(id, tx, commands_rx, devices_rx, worker_connected, control));
+        Slot {
+            kind: P::KIND,
+            id: P::ID,
+            address,
+            serial: String::new(),
+            open: P::open,
+            create: Self::new::<P>,
+            devices: devices_tx,
+            commands: Box::new(commands_tx),
+            connected,
+            thread: Some(thread)
+        }
+    }
+
+    fn connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    fn drives(&self, serial: &str) -> bool {
+        self.connected() && self.serial == serial
+    }
+
+    fn commands<C: Send + 'static>(&self) -> Sender<C> {
+        self.commands.downcast_ref::<Sender<C>>().expect("command type of slot").clone()
+    }
+}
+
+/// All slots plus what is needed to open panels, shared by the handle and the monitor.
+pub(crate) struct Registry {
+    api: HidApi,
+    /// Address of a simulator that replaces the USB panels.
+    #[cfg(feature = "simulator")]
+    simulator: Option<String>,
+    slots: Vec<Slot>,
+    /// User-chosen names by serial.
+    names: HashMap<String, String>,
+    /// Panels whose open error has been reported, so it is not repeated on every scan.
+    failed: HashSet<String>,
+    tx: Sender<InputData>,
+    control: Arc<Control>
+}
+
+impl Registry {
+    pub(crate) fn new(api: HidApi, names: HashMap<String, String>, tx: Sender<InputData>, control: Arc<Control>) -> Self {
+        Registry {
+            api,
+            #[cfg(feature = "simulator")]
+            simulator: std::env::var(crate::simulator::ENV).ok(),
+            slots: Vec::new(),
+            names,
+            failed: HashSet::new(),
+            tx,
+            control
+        }
+    }
+
+    /// Adds the slot for the first panel of model `P`, which takes any panel nobody else claims.
+    pub(crate) fn add<P: Panel>(&mut self) -> Sender<P::Command> {
+        let slot = Slot::new::<P>(None, None, self.tx.clone(), self.control.clone());
+        let commands = slot.commands();
+        self.slots.push(slot);
+        commands
+    }
+
+    /// Command sender of the panel of model `P` with the given name or serial.
+    ///
+    /// A panel already driven by the shared slot stays there, that slot is then reserved for it.
+    pub(crate) fn sender<P: Panel>(&mut self, device: &str) -> Sender<P::Command> {
+        let serial = self.names.iter()
+            .find(|(_, name)| name.as_str() == device)
+            .map_or(device, |(serial, _)| serial.as_str())
+            .to_string();
+        let index = match self.slots.iter().position(|slot| slot.kind == P::KIND && slot.address.as_deref() == Some(serial.as_str())) {
+            Some(index) => index,
+            None => match self.slots.iter().position(|slot| slot.kind == P::KIND && slot.drives(&serial)) {
+                Some(index) => {
+                    self.slots[index].address = Some(serial);
+                    index
+                },
+                None => {
+                    let name = self.names.get(&serial).cloned();
+                    self.slots.push(Slot::new::<P>(Some(serial), name, self.tx.clone(), self.control.clone()));
+                    self.slots.len() - 1
+                }
+            }
+        };
+        self.slots[index].commands()
+    }
+
+    pub(crate) fn is_connected(&self, kind: PanelKind) -> bool {
+        self.slots.iter().any(|slot| slot.kind == kind && slot.connected())
+    }
+
+    /// Ids of all panels currently driven.
+    pub(crate) fn devices(&self) -> Vec<DeviceId> {
+        self.slots.iter()
+            .filter(|slot| slot.connected())
+            .map(|slot| self.device_id(slot.kind, &slot.serial))
+            .collect()
+    }
+
+    fn device_id(&self, kind: PanelKind, serial: &str) -> DeviceId {
+        DeviceId { kind, serial: serial.to_string(), name: self.names.get(serial).cloned() }
+    }
+
+    /// Waits for all workers, after the control has been stopped.
+    pub(crate) fn join(&mut self) {
+        for slot in self.slots.iter_mut() {
+            if let Some(thread) = slot.thread.take() {
+                let _ = thread.join();
+            }
+        }
+    }
+
+    /// Opens every panel that is present but not driven yet.
+    pub(crate) fn scan(&mut self) {
+        #[cfg(feature = "simulator")]
+        if let Some(address) = self.simulator.clone() {
+            return self.scan_simulator(&address);
+        }
+        // a failed enumeration is simply retried on the next scan
+        if self.api.refresh_devices().is_err() {
+            return;
+        }
+        let models: Vec<(u16, u16)> = self.slots.iter().map(|slot| slot.id).collect();
+        let present: Vec<DeviceInfo> = self.api.device_list()
+            .filter(|info| models.contains(&(info.vendor_id(), info.product_id())))
+            .cloned()
+            .collect();
+        let serials: Vec<String> = present.iter().map(serial).collect();
+        self.failed.retain(|serial| serials.contains(serial));
+
+        for (info, serial) in present.iter().zip(serials) {
+            let Some(kind) = PanelKind::from_usb_id(info.vendor_id(), info.product_id()) else { continue };
+            let Some(index) = self.free_slot(kind, &serial) else { continue };
+            let device = (self.slots[index].open)(&self.api, info);
+            self.connect(index, serial, device);
+        }
+    }
+
+    /// Like `scan`, with the panels of a simulator instead of USB panels.
+    #[cfg(feature = "simulator")]
+    fn scan_simulator(&mut self, address: &str) {
+        // no simulator running is the same as no panels plugged in
+        let Ok(present) = crate::simulator::list(address) else { return };
+        self.failed.retain(|serial| present.iter().any(|(_, present)| present == serial));
+        for (kind, serial) in present {
+            if !self.slots.iter().any(|slot| slot.kind == kind) {
+                continue;
+            }
+            let Some(index) = self.free_slot(kind, &serial) else { continue };
+            let device = crate::simulator::open(address, kind, &serial).map(|device| Box::new(device) as Box<dyn Transport>);
+            self.connect(index, serial, device);
+        }
+    }
+
+    /// Slot that should drive the panel, `None` if it is driven already.
+    fn free_slot(&mut self, kind: PanelKind, serial: &str) -> Option<usize> {
+        if self.slots.iter().any(|slot| slot.kind == kind && slot.drives(serial)) {
+            return None;
+        }
+        let index = match self.slots.iter().position(|slot| slot.kind == kind && slot.address.as_deref() == Some(serial))
+            .or_else(|| self.slots.iter().position(|slot| slot.kind == kind && slot.address.is_none() && !slot.connected())) {
+            Some(index) => index,
+            None => {
+                // another panel of a model we already drive gets a slot of its own
+                let model = self.slots.iter().find(|slot| slot.kind == kind).expect("slot of model");
+                let slot = (model.create)(Some(serial.to_string()), self.names.get(serial).cloned(), self.tx.clone(), self.control.clone());
+                self.slots.push(slot);
+                self.slots.len() - 1
+            }
+        };
+        Some(index)
+    }
+
+    /// Hands an opened panel to the worker of its slot, or reports why it could not be opened.
+    fn connect(&mut self, index: usize, serial: String, device: Result<Box<dyn Transport>>) {
+        let device_id = self.device_id(self.slots[index].kind, &serial);
+        match device {
+            Ok(device) => {
+                let slot = &mut self.slots[index];
+                self.failed.remove(&serial);
+                slot.serial = serial;
+                slot.connected.store(true, Ordering::Relaxed);
+                if slot.devices.send((device_id, device)).is_err() {
+                    slot.connected.store(false, Ordering::Relaxed);
+                }
+            },
+            Err(error) => {
+                // report a panel we cannot open once, not on every scan
+                if self.failed.insert(serial) {
+                    let _ = self.tx.send(InputData::DeviceError(device_id, error));
+                }
+            }
+        }
+    }
+}
+
+/// Rescans for panels until stopped.
+pub(crate) fn monitor(registry: Arc<Mutex<Registry>>, control: Arc<Control>) {
+    let mut waited = Duration::ZERO;
+    while control.running() {
+        // short naps so a stop does not wait for a whole scan interval
+        thread::sleep(IDLE_INTERVAL);
+        waited += IDLE_INTERVAL;
+        if waited >= SCAN_INTERVAL {
+            waited = Duration::ZERO;
+            registry.lock().expect("registry poisoned").scan();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::radio_panel::{OutputCommands, RadioPanel};
+    use crate::transport::MockTransport;
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    #[test]
+    fn replays_state_after_reconnect() {
+        let (tx, rx) = mpsc::channel();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        let (devices_tx, devices_rx) = mpsc::channel::<(DeviceId, Box<dyn Transport>)>();
+        let id = DeviceId::new(PanelKind::Radio, "1234");
+        let connected = Arc::new(AtomicBool::new(true));
+        let control = Control::new();
+        let worker_connected = connected.clone();
+        let worker_control = control.clone();
+        let worker_id = id.clone();
+        thread::spawn(move || worker::<RadioPanel>(worker_id, tx, cmd_rx, devices_rx, worker_connected, worker_control));
+
+        let first = MockTransport::new();
+        devices_tx.send((id.clone(), Box::new(first.clone()))).unwrap();
+        assert_eq!(rx.recv_timeout(TIMEOUT), Ok(InputData::DeviceConnected(id.clone())));
+        assert!(first.next_feature_report(TIMEOUT).is_some());
+        cmd_tx.send(OutputCommands::SetUpperActiveFrequency(118.25)).unwrap();
+        let report = first.next_feature_report(TIMEOUT).expect("no report sent");
+
+        first.disconnect();
+        assert_eq!(rx.recv_timeout(TIMEOUT), Ok(InputData::DeviceError(id.clone(), Error::Io("device disconnected".to_string()))));
+        assert_eq!(rx.recv_timeout(TIMEOUT), Ok(InputData::DeviceDisconnected(id.clone())));
+        assert!(!connected.load(Ordering::Relaxed));
+
+        let second = MockTransport::new();
+        devices_tx.send((id.clone(), Box::new(second.clone()))).unwrap();
+        assert_eq!(rx.recv_timeout(TIMEOUT), Ok(InputData::DeviceConnected(id.clone())));
+        assert_eq!(second.next_feature_report(TIMEOUT), Some(report));
+        control.stop();
+    }
+
+    #[test]
+    fn coalesces_queued_commands() {
+        let (tx, _rx) = mpsc::channel();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        cmd_tx.send(OutputCommands::SetUpperActiveFrequency(118.25)).unwrap();
+        cmd_tx.send(OutputCommands::SetUpperStandbyFrequency(121.5)).unwrap();
+        cmd_tx.send(OutputCommands::SetLowerActiveFrequency(110.3)).unwrap();
+        let mock = MockTransport::new();
+        let device = mock.clone();
+        thread::spawn(move || {
+            let mut panel = RadioPanel::default();
+            run(&mut panel, &DeviceId::new(PanelKind::Radio, ""), &device, &tx, &cmd_rx, &Control::new())
+        });
+
+        assert!(mock.next_feature_report(TIMEOUT).is_some());
+        let report = mock.next_feature_report(TIMEOUT).expect("no report sent");
+        assert_eq!(report[1..6], [1, 1, 0xD8, 2, 5]);
+        assert_eq!(report[6..11], [1, 2, 0xD1, 5, 0]);
+        assert_eq!(report[11..16], [1, 1, 0xD0, 3, 0]);
+        assert_eq!(mock.next_feature_report(Duration::from_millis(100)), None);
+    }
+
+    #[test]
+    fn shows_commands_sent_before_stop() {
+        let (tx, _rx) = mpsc::channel();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        cmd_tx.send(OutputCommands::SetUpperActiveFrequency(118.25)).unwrap();
+        let control = Control::new();
+        control.stop();
+        let mock = MockTransport::new();
+        let mut panel = RadioPanel::default();
+        run(&mut panel, &DeviceId::new(PanelKind::Radio, ""), &mock, &tx, &cmd_rx, &control).unwrap();
+
+        assert!(mock.next_feature_report(TIMEOUT).is_some());
+        let report = mock.next_feature_report(TIMEOUT).expect("no report sent");
+        assert_eq!(report[1..6], [1, 1, 0xD8, 2, 5]);
+    }
+
+    #[test]
+    fn reports_failed_last_update_as_error() {
+        let (tx, rx) = mpsc::channel();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        cmd_tx.send(OutputCommands::SetUpperActiveFrequency(118.25)).unwrap();
+        let control = Control::new();
+        control.stop();
+        let mock = MockTransport::new();
+        mock.disconnect();
+        let mut panel = RadioPanel::default();
+        let id = DeviceId::new(PanelKind::Radio, "");
+        assert_eq!(run(&mut panel, &id, &mock, &tx, &cmd_rx, &control), Ok(()));
+
+        let inputs: Vec<InputData> = rx.try_iter().collect();
+        assert!(!inputs.is_empty());
+        assert!(inputs.iter().all(|input| *input == InputData::DeviceError(id.clone(), Error::Io("device disconnected".to_string()))));
+    }
+}
@@ -0,0 +1,182 @@
+use rusb::{Direction, GlobalContext, TransferType};
+use std::time::Duration;
+use crate::PanelKind;
+use crate::error::{Error, Result};
+
+/*
+DirectOutput requests: bulk OUT transfers on the vendor specific interface (endpoint 0x02)
+all fields are 32 bit big endian words
+
+word 0: 0
+word 1: page the request refers to (1 = default page)
+words 2-4: 0
+word 5: 0x18    // header length, the request payload starts at word 6
+word 6: request
+words 7..: request arguments
+
+set led: request 3 (4 in the capture of the down arrow LED), led index, state, 2 words padding -> 44 bytes
+add page: request 1, page, flags (1 = make it the active page)
+remove page: request 2, page
+set active page: request 6, page
+set image: request 5, page, image index, data length, then the image data
+  image data: 320x240 pixels, 24 bit BGR, bottom row first (230400 bytes)
+
+only the set led request has been captured so far (see flight_instrument_panel.rs), and request 4
+for the down arrow LED was seen in that single capture, it needs a second one to confirm it
+the page and image request codes are guesses following the layout of the DirectOutput SDK calls,
+so frame output is not delivered: the requests are only built with the experimental-fip-images
+feature until a capture confirms them
+*/
+
+const BULK_OUT: u8 = 0x02;
+const TIMEOUT: Duration = Duration::from_millis(500);
+const HEADER_LENGTH: u32 = 0x18;
+pub const DEFAULT_PAGE: u32 = 1;
+
+const REQUEST_SET_LED: u32 = 3;
+#[cfg(feature = "experimental-fip-images")]
+const REQUEST_ADD_PAGE: u32 = 1;
+#[cfg(feature = "experimental-fip-images")]
+const REQUEST_REMOVE_PAGE: u32 = 2;
+#[cfg(feature = "experimental-fip-images")]
+const REQUEST_SET_IMAGE: u32 = 5;
+#[cfg(feature = "experimental-fip-images")]
+const REQUEST_SET_ACTIVE_PAGE: u32 = 6;
+#[cfg(feature = "experimental-fip-images")]
+const FLAG_SET_AS_ACTIVE: u32 = 1;
+// seen in a single capture only, see above
+const REQUEST_SET_LED_DOWN_ARROW: u32 = 4;
+const DOWN_ARROW_LED: u8 = 8;
+
+/// Vendor specific bulk channel of a DirectOutput device such as the Flight Instrument Panel.
+///
+/// The FIP only reports its buttons over HID, everything it displays is sent over this channel.
+pub struct DirectOutputDevice {
+    handle: rusb::DeviceHandle<GlobalContext>,
+    interface: u8
+}
+
+impl DirectOutputDevice {
+    /// Opens the device with the given ids and claims the interface owning the bulk OUT endpoint.
+    ///
+    /// Without a serial number the first matching device is used.
+    pub fn open(vendor_id: u16, product_id: u16, serial: Option<&str>) -> Result<Self> {
+        let handle = find(vendor_id, product_id, serial)?.ok_or(Error::DeviceNotFound(PanelKind::FlightInstrument))?;
+        let config = handle.device().active_config_descriptor()?;
+        let interface = config.interfaces()
+            .flat_map(|interface| interface.descriptors())
+            .find(|descriptor| descriptor.endpoint_descriptors().any(|endpoint|
+                endpoint.address() == BULK_OUT && endpoint.direction() == Direction::Out && endpoint.transfer_type() == TransferType::Bulk
+            ))
+            .map(|descriptor| descriptor.interface_number())
+            .ok_or(Error::Io("device has no DirectOutput endpoint".to_string()))?;
+        let _ = handle.set_auto_detach_kernel_driver(true);
+        handle.claim_interface(interface).map_err(|error| match error {
+            rusb::Error::Access => Error::PermissionDenied(PanelKind::FlightInstrument),
+            error => Error::from(error)
+        })?;
+        Ok(DirectOutputDevice { handle, interface })
+    }
+
+    pub fn write(&self, data: &[u8]) -> Result<usize> {
+        Ok(self.handle.write_bulk(BULK_OUT, data, TIMEOUT)?)
+    }
+}
+
+fn find(vendor_id: u16, product_id: u16, serial: Option<&str>) -> Result<Option<rusb::DeviceHandle<GlobalContext>>> {
+    // a device we may not open might be the one asked for, so its error is kept for the end
+    let mut open_error = None;
+    for device in rusb::devices()?.iter() {
+        let descriptor = device.device_descriptor()?;
+        if descriptor.vendor_id() != vendor_id || descriptor.product_id() != product_id {
+            continue;
+        }
+        let handle = match device.open() {
+            Ok(handle) => handle,
+            Err(error) => {
+                open_error = Some(error);
+                continue;
+            }
+        };
+        match serial {
+            Some(serial) if handle.read_serial_number_string_ascii(&descriptor).ok().as_deref() != Some(serial) => continue,
+            _ => return Ok(Some(handle))
+        }
+    }
+    match open_error {
+        Some(rusb::Error::Access) => Err(Error::PermissionDenied(PanelKind::FlightInstrument)),
+        Some(error) => Err(Error::from(error)),
+        None => Ok(None)
+    }
+}
+
+impl Drop for DirectOutputDevice {
+    fn drop(&mut self) {
+        let _ = self.handle.release_interface(self.interface);
+    }
+}
+
+fn request(page: u32, request: u32, arguments: &[u32]) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::with_capacity(HEADER_LENGTH as usize + 4 + arguments.len() * 4);
+    for word in [0, page, 0, 0, 0, HEADER_LENGTH, request].iter().chain(arguments) {
+        data.extend_from_slice(&word.to_be_bytes());
+    }
+    data
+}
+
+/// Request switching LED `led` (0-8) on or off.
+pub fn set_led_request(led: u8, on: bool) -> Vec<u8> {
+    let code = if led == DOWN_ARROW_LED { REQUEST_SET_LED_DOWN_ARROW } else { REQUEST_SET_LED };
+    request(DEFAULT_PAGE, code, &[led as u32, on as u32, 0, 0])
+}
+
+#[cfg(feature = "experimental-fip-images")]
+pub fn add_page_request(page: u32, activate: bool) -> Vec<u8> {
+    request(page, REQUEST_ADD_PAGE, &[page, if activate { FLAG_SET_AS_ACTIVE } else { 0 }])
+}
+
+#[cfg(feature = "experimental-fip-images")]
+pub fn remove_page_request(page: u32) -> Vec<u8> {
+    request(page, REQUEST_REMOVE_PAGE, &[page])
+}
+
+#[cfg(feature = "experimental-fip-images")]
+pub fn set_active_page_request(page: u32) -> Vec<u8> {
+    request(page, REQUEST_SET_ACTIVE_PAGE, &[page])
+}
+
+/// Request showing `image` (already in device layout) on `page`.
+#[cfg(feature = "experimental-fip-images")]
+pub fn set_image_request(page: u32, image: &[u8]) -> Vec<u8> {
+    let mut data = request(page, REQUEST_SET_IMAGE, &[page, 0, image.len() as u32]);
+    data.extend_from_slice(image);
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_hex(hex: &str) -> Vec<u8> {
+        (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap()).collect()
+    }
+
+    #[test]
+    fn matches_capture() {
+        assert_eq!(set_led_request(1, true), from_hex("0000000000000001000000000000000000000000000000180000000300000001000000010000000000000000"));
+        assert_eq!(set_led_request(6, false), from_hex("0000000000000001000000000000000000000000000000180000000300000006000000000000000000000000"));
+        assert_eq!(set_led_request(8, true), from_hex("0000000000000001000000000000000000000000000000180000000400000008000000010000000000000000"));
+    }
+
+    #[cfg(feature = "experimental-fip-images")]
+    #[test]
+    fn image_request() {
+        let image = [0xAB; 6];
+        let data = set_image_request(2, &image);
+        assert_eq!(data.len(), 40 + image.len());
+        assert_eq!(data[4..8], 2u32.to_be_bytes());
+        assert_eq!(data[24..28], REQUEST_SET_IMAGE.to_be_bytes());
+        assert_eq!(data[36..40], 6u32.to_be_bytes());
+        assert_eq!(data[40..], image);
+    }
+}
@@ -0,0 +1,49 @@
+use hidapi::HidApi;
+use crate::{DeviceId, PanelKind};
+use crate::error::Result;
+
+/// A panel found by [`discover`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PanelInfo {
+    pub kind: PanelKind,
+    /// Serial number as reported by the panel, `None` if it reports none.
+    pub serial: Option<String>,
+    /// Platform specific path of the HID device.
+    pub path: String,
+    /// Release number of the device (bcdDevice), i.e. its firmware version.
+    pub release_number: u16,
+    /// Whether the panel could be opened, false usually means missing udev rules.
+    pub accessible: bool
+}
+
+impl PanelInfo {
+    /// The id its events carry once it is driven by `Flightpanels`.
+    pub fn id(&self) -> DeviceId {
+        DeviceId::new(self.kind, self.serial.clone().unwrap_or_else(|| self.path.clone()))
+    }
+}
+
+/// Lists all connected panels without starting any threads.
+pub fn discover() -> Result<Vec<PanelInfo>> {
+    discover_with(&HidApi::new()?)
+}
+
+/// Like [`discover`] with an existing hidapi context.
+pub fn discover_with(api: &HidApi) -> Result<Vec<PanelInfo>> {
+    let mut panels = Vec::new();
+    for info in api.device_list() {
+        let Some(kind) = PanelKind::from_usb_id(info.vendor_id(), info.product_id()) else {
+            continue;
+        };
+        // opening is the only way to find out, the device is closed again right away
+        let accessible = api.open_path(info.path()).is_ok();
+        panels.push(PanelInfo {
+            kind,
+            serial: info.serial_number().filter(|serial| !serial.is_empty()).map(str::to_string),
+            path: info.path().to_string_lossy().into_owned(),
+            release_number: info.release_number(),
+            accessible
+        });
+    }
+    Ok(panels)
+}
@@ -0,0 +1,121 @@
+use crate::segments::{self, BLANK, DASH, NumberFormat};
+
+/*
+DME on a radio panel row (selector on DME), fed by the application
+
+left display: the source (1 for NAV1, 2 for NAV2) in the first digit, the distance with one
+decimal in the other four, 0.0-999.9 NM
+right display: ground speed in whole knots or time to station in whole minutes, right aligned,
+the swap button switches between the two
+
+without a signal, or for a value that does not fit, the digits of the value show dashes, e.g.
+"1 ---" and "  ---", never zeros that could be taken for a reading (the dash code is the multi
+panel's, see segments.rs)
+*/
+
+/// Dashes of a missing value, right aligned.
+const NO_VALUE: [u8; 5] = [BLANK, BLANK, DASH, DASH, DASH];
+
+/// NAV receiver the DME is paired with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DmeSource {
+    Nav1,
+    Nav2
+}
+
+impl DmeSource {
+    fn digit(&self) -> u8 {
+        match self {
+            DmeSource::Nav1 => 1,
+            DmeSource::Nav2 => 2
+        }
+    }
+}
+
+/// What the right display of a DME row shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum DmeStandby {
+    Speed,
+    TimeToStation
+}
+
+/// What the DME receives, `None` where the application has no value.
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct DmeReading {
+    /// NM
+    pub distance: Option<f32>,
+    /// Ground speed in knots.
+    pub speed: Option<f32>,
+    /// Minutes.
+    pub time_to_station: Option<f32>
+}
+
+/// DME state of a `RadioStack`.
+pub(crate) struct Dme {
+    pub(crate) source: DmeSource,
+    /// `None` without a signal.
+    pub(crate) reading: Option<DmeReading>,
+    pub(crate) standby: DmeStandby
+}
+
+impl Default for Dme {
+    fn default() -> Self {
+        Dme { source: DmeSource::Nav1, reading: None, standby: DmeStandby::Speed }
+    }
+}
+
+impl Dme {
+    /// The swap button switches the right display between speed and time.
+    pub(crate) fn press_swap(&mut self) {
+        self.standby = match self.standby {
+            DmeStandby::Speed => DmeStandby::TimeToStation,
+            DmeStandby::TimeToStation => DmeStandby::Speed
+        };
+    }
+
+    /// Left and right display.
+    pub(crate) fn displays(&self) -> ([u8; 5], [u8; 5]) {
+        let reading = self.reading.unwrap_or_default();
+        let mut distance = value(reading.distance, 1, 999.95);
+        distance[0] = self.source.digit();
+        let standby = match self.standby {
+            DmeStandby::Speed => reading.speed,
+            DmeStandby::TimeToStation => reading.time_to_station
+        };
+        (distance, value(standby, 0, 99999.5))
+    }
+}
+
+/// Right aligned with `decimals` decimals, dashes if missing, negative or `limit` or more.
+fn value(value: Option<f32>, decimals: usize, limit: f64) -> [u8; 5] {
+    value.map(f64::from)
+        .filter(|value| (0.0..limit).contains(value))
+        .and_then(|value| segments::number(value, NumberFormat::new(decimals)))
+        .unwrap_or(NO_VALUE)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn displays() {
+        let mut dme = Dme::default();
+        assert_eq!(dme.displays(), ([1, BLANK, DASH, DASH, DASH], NO_VALUE));
+
+        dme.source = DmeSource::Nav2;
+        dme.reading = Some(DmeReading { distance: Some(12.34), speed: Some(245.5), time_to_station: Some(2.9) });
+        assert_eq!(dme.displays(), ([2, BLANK, 1, 0xD2, 3], [BLANK, BLANK, 2, 4, 6]));
+        dme.press_swap();
+        assert_eq!(dme.displays().1, [BLANK, BLANK, BLANK, BLANK, 3]);
+
+        dme.reading = Some(DmeReading { distance: Some(0.04), speed: Some(0.0), time_to_station: None });
+        assert_eq!(dme.displays(), ([2, BLANK, BLANK, 0xD0, 0], NO_VALUE));
+        dme.reading = Some(DmeReading { distance: Some(999.96), ..DmeReading::default() });
+        assert_eq!(dme.displays().0, [2, BLANK, DASH, DASH, DASH]);
+        dme.reading = Some(DmeReading { distance: Some(999.94), ..DmeReading::default() });
+        assert_eq!(dme.displays().0, [2, 9, 9, 0xD9, 9]);
+        dme.reading = Some(DmeReading { distance: Some(-1.0), ..DmeReading::default() });
+        assert_eq!(dme.displays().0, [2, BLANK, DASH, DASH, DASH]);
+    }
+}
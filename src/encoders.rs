@@ -0,0 +1,203 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use crate::{DeviceId, InputData};
+use crate::events::{Event, InputEvent};
+use crate::flight_instrument_panel::FlightInstrumentPanelInputs;
+use crate::multi_panel::MultiPanelInputs;
+use crate::radio_panel::{RadioPanelInputs, RadioRow};
+
+/*
+Rotary encoders: every knob reports a detent by setting its inc or dec bit in
+an input report. When the knob is turned quickly the bit stays set over several
+reports in a row, each of them one detent, so a detent is counted for every
+report with the bit set, not only when it rises. Every report has to be decoded
+once, a report decoded twice counts its detents twice.
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Knob {
+    /// Inner knob of a radio.
+    Fine(RadioRow),
+    /// Outer knob of a radio.
+    Coarse(RadioRow),
+    /// Knob of the multi panel.
+    Jog,
+    /// Pitch trim wheel of the multi panel, positive is nose up.
+    PitchTrim,
+    FipLeft,
+    FipRight
+}
+
+/// Steps per detent depending on how quickly the detents follow each other.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Acceleration {
+    // (interval below which the factor applies, factor), shortest interval first
+    steps: Vec<(Duration, i32)>
+}
+
+impl Acceleration {
+    /// A curve from `(interval, factor)` pairs: detents less than `interval` apart count `factor` steps.
+    pub fn new(steps: impl IntoIterator<Item = (Duration, i32)>) -> Self {
+        let mut steps: Vec<(Duration, i32)> = steps.into_iter().collect();
+        steps.sort_by_key(|(interval, _)| *interval);
+        Acceleration { steps }
+    }
+
+    /// Steps for a detent following the previous one in the same direction after `interval`.
+    pub fn factor(&self, interval: Option<Duration>) -> i32 {
+        interval
+            .and_then(|interval| self.steps.iter().find(|(limit, _)| interval < *limit))
+            .map_or(1, |(_, factor)| *factor)
+    }
+}
+
+impl Default for Acceleration {
+    /// 1 step when turned slowly, 4 when turned briskly and 10 when spun.
+    fn default() -> Self {
+        Acceleration::new([(Duration::from_millis(40), 10), (Duration::from_millis(100), 4)])
+    }
+}
+
+/// Accessors of the inc and dec bit of a knob.
+type Detents<S> = (fn(&S) -> bool, fn(&S) -> bool, Knob);
+
+const RADIO_PANEL_KNOBS: [Detents<RadioPanelInputs>; 4] = [
+    (RadioPanelInputs::fine_inc1, RadioPanelInputs::fine_dec1, Knob::Fine(RadioRow::Upper)),
+    (RadioPanelInputs::coarse_inc1, RadioPanelInputs::coarse_dec1, Knob::Coarse(RadioRow::Upper)),
+    (RadioPanelInputs::fine_inc2, RadioPanelInputs::fine_dec2, Knob::Fine(RadioRow::Lower)),
+    (RadioPanelInputs::coarse_inc2, RadioPanelInputs::coarse_dec2, Knob::Coarse(RadioRow::Lower))
+];
+
+const MULTI_PANEL_KNOBS: [Detents<MultiPanelInputs>; 2] = [
+    (MultiPanelInputs::jog_inc, MultiPanelInputs::jog_dec, Knob::Jog),
+    (MultiPanelInputs::pitch_up, MultiPanelInputs::pitch_down, Knob::PitchTrim)
+];
+
+const FIP_KNOBS: [Detents<FlightInstrumentPanelInputs>; 2] = [
+    (FlightInstrumentPanelInputs::left_encoder_inc, FlightInstrumentPanelInputs::left_encoder_dec, Knob::FipLeft),
+    (FlightInstrumentPanelInputs::right_encoder_inc, FlightInstrumentPanelInputs::right_encoder_dec, Knob::FipRight)
+];
+
+/// Counts knob detents in the snapshots of `Flightpanels::inputs()`.
+///
+/// Emits `Event::KnobTurned` with the signed detent count and the steps after
+/// acceleration, which equal the detents for knobs without a curve.
+#[derive(Default)]
+pub struct Encoders {
+    // time and direction of the last detent per knob
+    last_detent: HashMap<(DeviceId, Knob), (Instant, i32)>,
+    acceleration: HashMap<Knob, Acceleration>
+}
+
+impl Encoders {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_acceleration(&mut self, knob: Knob, curve: Acceleration) {
+        self.acceleration.insert(knob, curve);
+    }
+
+    pub fn decode(&mut self, input: &InputData) -> Vec<InputEvent> {
+        self.decode_at(input, Instant::now())
+    }
+
+    /// Like `decode` for a report received at `now`, e.g. when replaying a recording.
+    pub fn decode_at(&mut self, input: &InputData, now: Instant) -> Vec<InputEvent> {
+        let detents = match input {
+            InputData::RadioInputData(_, inputs) => detents(inputs, &RADIO_PANEL_KNOBS),
+            InputData::MultiInputData(_, inputs) => detents(inputs, &MULTI_PANEL_KNOBS),
+            InputData::FIPInputData(_, inputs) => detents(inputs, &FIP_KNOBS),
+            InputData::SwitchInputData(..) | InputData::DeviceError(..)
+            | InputData::DeviceConnected(_) | InputData::DeviceDisconnected(_) => return Vec::new()
+        };
+        let device = input.device();
+
+        detents.into_iter().map(|(knob, direction)| {
+            let key = (device.clone(), knob);
+            let interval = self.last_detent.get(&key)
+                .filter(|(_, last_direction)| *last_direction == direction)
+                .map(|(last, _)| now.saturating_duration_since(*last));
+            self.last_detent.insert(key, (now, direction));
+            let factor = self.acceleration.get(&knob).map_or(1, |curve| curve.factor(interval));
+            InputEvent { device: device.clone(), event: Event::KnobTurned { knob, detents: direction, steps: direction * factor } }
+        }).collect()
+    }
+}
+
+fn detents<S>(inputs: &S, table: &[Detents<S>]) -> Vec<(Knob, i32)> {
+    let mut detents = Vec::new();
+    for (inc, dec, knob) in table {
+        if inc(inputs) {
+            detents.push((*knob, 1));
+        }
+        if dec(inputs) {
+            detents.push((*knob, -1));
+        }
+    }
+    detents
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PanelKind;
+
+    fn steps(events: Vec<InputEvent>) -> Vec<(Knob, i32, i32)> {
+        events.into_iter().map(|event| match event.event {
+            Event::KnobTurned { knob, detents, steps } => (knob, detents, steps),
+            event => panic!("unexpected {:?}", event)
+        }).collect()
+    }
+
+    #[test]
+    fn counts_every_report() {
+        let id = DeviceId::new(PanelKind::Radio, "1");
+        let mut encoders = Encoders::new();
+        let turned = RadioPanelInputs::new().with_fine_inc1(true).with_coarse_dec2(true);
+        assert_eq!(steps(encoders.decode(&InputData::RadioInputData(id.clone(), turned))), vec![
+            (Knob::Fine(RadioRow::Upper), 1, 1),
+            (Knob::Coarse(RadioRow::Lower), -1, -1)
+        ]);
+        assert_eq!(steps(encoders.decode(&InputData::RadioInputData(id.clone(), RadioPanelInputs::new()))), vec![]);
+        assert_eq!(steps(encoders.decode(&InputData::RadioInputData(id, turned.with_swap1(true)))).len(), 2);
+    }
+
+    #[test]
+    fn held_bit_counts_each_report() {
+        let id = DeviceId::new(PanelKind::Multi, "1");
+        let mut encoders = Encoders::new();
+        let spun = MultiPanelInputs::new().with_jog_inc(true);
+        let detents: i32 = (0..4)
+            .flat_map(|_| steps(encoders.decode(&InputData::MultiInputData(id.clone(), spun))))
+            .map(|(_, detents, _)| detents)
+            .sum();
+        assert_eq!(detents, 4);
+        // another button changing while the knob spins counts as well
+        assert_eq!(steps(encoders.decode(&InputData::MultiInputData(id, spun.with_hdg(true)))), vec![(Knob::Jog, 1, 1)]);
+    }
+
+    #[test]
+    fn acceleration() {
+        let id = DeviceId::new(PanelKind::Multi, "1");
+        let mut encoders = Encoders::new();
+        encoders.set_acceleration(Knob::Jog, Acceleration::default());
+        let start = Instant::now();
+        let mut turn = |inputs: MultiPanelInputs, at: u64| {
+            let events = encoders.decode_at(&InputData::MultiInputData(id.clone(), inputs), start + Duration::from_millis(at));
+            encoders.decode_at(&InputData::MultiInputData(id.clone(), MultiPanelInputs::new()), start + Duration::from_millis(at + 1));
+            steps(events)
+        };
+        let inc = MultiPanelInputs::new().with_jog_inc(true);
+        let dec = MultiPanelInputs::new().with_jog_dec(true);
+        assert_eq!(turn(inc, 0), vec![(Knob::Jog, 1, 1)]);
+        assert_eq!(turn(inc, 500), vec![(Knob::Jog, 1, 1)]);
+        assert_eq!(turn(inc, 560), vec![(Knob::Jog, 1, 4)]);
+        assert_eq!(turn(inc, 580), vec![(Knob::Jog, 1, 10)]);
+        // reversing starts slow again
+        assert_eq!(turn(dec, 590), vec![(Knob::Jog, -1, -1)]);
+        // no curve for the trim wheel
+        assert_eq!(turn(MultiPanelInputs::new().with_pitch_up(true), 600), vec![(Knob::PitchTrim, 1, 1)]);
+        assert_eq!(turn(MultiPanelInputs::new().with_pitch_up(true), 605), vec![(Knob::PitchTrim, 1, 1)]);
+    }
+}
@@ -0,0 +1,118 @@
+use std::fmt;
+use std::sync::mpsc::{RecvError, SendError};
+use crate::PanelKind;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// The panel is not connected.
+    DeviceNotFound(PanelKind),
+    /// The panel is connected but the user may not open it (check the udev rules).
+    ///
+    /// Recognized when hidapi fails with an `io::ErrorKind::PermissionDenied` error or a
+    /// "Permission denied" message (as hidraw on Linux does), and when libusb denies access to
+    /// the FIP's DirectOutput channel. Platforms that word the failure differently report `Io`.
+    PermissionDenied(PanelKind),
+    /// Reading from or writing to a device failed.
+    Io(String),
+    /// The value cannot be shown on the named display.
+    ValueOutOfRange { display: &'static str, value: f64 },
+    /// The text or digit codes cannot be shown on the named display.
+    InvalidDisplayText { display: &'static str, text: String },
+    /// A FIP frame must be exactly 320x240 RGB pixels, holds the number of bytes given.
+    InvalidFrameSize(usize),
+    /// A recording could not be parsed, `line` counts from 1.
+    InvalidRecording { line: usize, message: String },
+    /// The other side of a channel has been dropped.
+    ChannelClosed
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::DeviceNotFound(kind) => write!(f, "{} not found", kind),
+            Error::PermissionDenied(kind) => write!(f, "no permission to open {}", kind),
+            Error::Io(message) => write!(f, "I/O error: {}", message),
+            Error::ValueOutOfRange { display, value } => write!(f, "{} cannot be shown on the {} display", value, display),
+            Error::InvalidDisplayText { display, text } => write!(f, "\"{}\" cannot be shown on the {} display", text, display),
+            Error::InvalidFrameSize(size) => write!(f, "frame has {} bytes, expected 320x240 RGB pixels", size),
+            Error::InvalidRecording { line, message } => write!(f, "invalid recording, line {}: {}", line, message),
+            Error::ChannelClosed => write!(f, "channel closed")
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<hidapi::HidError> for Error {
+    fn from(error: hidapi::HidError) -> Self {
+        Error::Io(error.to_string())
+    }
+}
+
+impl From<rusb::Error> for Error {
+    fn from(error: rusb::Error) -> Self {
+        Error::Io(error.to_string())
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Io(error.to_string())
+    }
+}
+
+impl<T> From<SendError<T>> for Error {
+    fn from(_: SendError<T>) -> Self {
+        Error::ChannelClosed
+    }
+}
+
+impl From<RecvError> for Error {
+    fn from(_: RecvError) -> Self {
+        Error::ChannelClosed
+    }
+}
+
+/// Tells apart a missing panel from one we are not allowed to open.
+pub(crate) fn open_error(api: &hidapi::HidApi, kind: PanelKind, id: (u16, u16), error: hidapi::HidError) -> Error {
+    if !api.device_list().any(|device| (device.vendor_id(), device.product_id()) == id) {
+        Error::DeviceNotFound(kind)
+    }
+    else if permission_denied(&error) {
+        Error::PermissionDenied(kind)
+    }
+    else {
+        Error::from(error)
+    }
+}
+
+/// Whether opening failed for lack of permission.
+///
+/// The native backends of hidapi give an `io::Error`, whose kind is checked. The C library only
+/// gives a message, which on Linux ends in the `strerror` text of EACCES, "Permission denied".
+/// Matching it is a Linux-only best effort, other platforms word it differently and get `Io`.
+fn permission_denied(error: &hidapi::HidError) -> bool {
+    match error {
+        hidapi::HidError::IoError { error } => error.kind() == std::io::ErrorKind::PermissionDenied,
+        hidapi::HidError::HidApiError { message } => message.contains("Permission denied"),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hidapi::HidError;
+    use std::io;
+
+    #[test]
+    fn permission() {
+        assert!(permission_denied(&HidError::IoError { error: io::Error::from(io::ErrorKind::PermissionDenied) }));
+        assert!(!permission_denied(&HidError::IoError { error: io::Error::from(io::ErrorKind::NotFound) }));
+        assert!(permission_denied(&HidError::HidApiError { message: "Failed to open a device with path '/dev/hidraw3': Permission denied".to_string() }));
+        assert!(!permission_denied(&HidError::HidApiError { message: "hid_open_path: failed to open IOHIDDevice".to_string() }));
+        assert!(!permission_denied(&HidError::HidApiErrorEmpty));
+    }
+}
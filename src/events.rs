@@ -0,0 +1,297 @@
+use std::collections::HashMap;
+use std::time::Instant;
+use crate::{DeviceId, InputData};
+use crate::encoders::{Acceleration, Encoders, Knob};
+use crate::flight_instrument_panel::FlightInstrumentPanelInputs;
+use crate::multi_panel::{MultiPanelInputs, SettingSelection};
+use crate::radio_panel::{ComSelection, RadioPanelInputs, RadioRow};
+use crate::switch_panel::{EngineSelection, SwitchPanelInputs};
+
+/// Something that changed on a panel between two reports.
+#[derive(Debug, Clone, PartialEq)]
+pub struct InputEvent {
+    pub device: DeviceId,
+    pub event: Event
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Event {
+    SwitchToggled { switch: Switch, on: bool },
+    ButtonPressed(Button),
+    ButtonReleased(Button),
+    SelectorChanged(Selector),
+    /// `detents` is +1 or -1, `steps` the detents after acceleration.
+    KnobTurned { knob: Knob, detents: i32, steps: i32 }
+}
+
+/// Toggle switches and lever positions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Switch {
+    Battery,
+    Alternator,
+    Avionics,
+    FuelPump,
+    DeIce,
+    PitotHeat,
+    Cowl,
+    PanelLights,
+    BeaconLights,
+    NavigationLights,
+    StrobeLights,
+    TaxiLights,
+    LandingLights,
+    GearUp,
+    GearDown,
+    AutoThrottle,
+    FlapsUp,
+    FlapsDown
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum MultiButton {
+    Ap,
+    Hdg,
+    Nav,
+    Ias,
+    Alt,
+    Vs,
+    Apr,
+    Rev
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum FipButton {
+    S1,
+    S2,
+    S3,
+    S4,
+    S5,
+    S6,
+    Up,
+    Down
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    Multi(MultiButton),
+    /// The active/standby swap button of a radio.
+    Swap(RadioRow),
+    Fip(FipButton)
+}
+
+impl From<MultiButton> for Button {
+    fn from(button: MultiButton) -> Self {
+        Button::Multi(button)
+    }
+}
+
+impl From<FipButton> for Button {
+    fn from(button: FipButton) -> Self {
+        Button::Fip(button)
+    }
+}
+
+/// Rotary selectors and their new position.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Selector {
+    Radio(RadioRow, ComSelection),
+    Multi(SettingSelection),
+    Engine(EngineSelection)
+}
+
+/// Accessor of one bit of an input report and what it stands for.
+type Bit<S, T> = (fn(&S) -> bool, T);
+
+const SWITCH_PANEL_SWITCHES: [Bit<SwitchPanelInputs, Switch>; 15] = [
+    (SwitchPanelInputs::battery, Switch::Battery),
+    (SwitchPanelInputs::alt, Switch::Alternator),
+    (SwitchPanelInputs::avionics, Switch::Avionics),
+    (SwitchPanelInputs::fuel_pump, Switch::FuelPump),
+    (SwitchPanelInputs::de_ice, Switch::DeIce),
+    (SwitchPanelInputs::pitot_heat, Switch::PitotHeat),
+    (SwitchPanelInputs::cowl, Switch::Cowl),
+    (SwitchPanelInputs::panel_lights, Switch::PanelLights),
+    (SwitchPanelInputs::beacon_lights, Switch::BeaconLights),
+    (SwitchPanelInputs::navigation_lights, Switch::NavigationLights),
+    (SwitchPanelInputs::strobe_lights, Switch::StrobeLights),
+    (SwitchPanelInputs::taxi_lights, Switch::TaxiLights),
+    (SwitchPanelInputs::landing_lights, Switch::LandingLights),
+    (SwitchPanelInputs::gear_up, Switch::GearUp),
+    (SwitchPanelInputs::gear_down, Switch::GearDown)
+];
+
+const MULTI_PANEL_SWITCHES: [Bit<MultiPanelInputs, Switch>; 3] = [
+    (MultiPanelInputs::auto_throttle, Switch::AutoThrottle),
+    (MultiPanelInputs::flaps_up, Switch::FlapsUp),
+    (MultiPanelInputs::flaps_down, Switch::FlapsDown)
+];
+
+const MULTI_PANEL_BUTTONS: [Bit<MultiPanelInputs, MultiButton>; 8] = [
+    (MultiPanelInputs::ap, MultiButton::Ap),
+    (MultiPanelInputs::hdg, MultiButton::Hdg),
+    (MultiPanelInputs::nav, MultiButton::Nav),
+    (MultiPanelInputs::ias, MultiButton::Ias),
+    (MultiPanelInputs::alt, MultiButton::Alt),
+    (MultiPanelInputs::vs, MultiButton::Vs),
+    (MultiPanelInputs::apr, MultiButton::Apr),
+    (MultiPanelInputs::rev, MultiButton::Rev)
+];
+
+const RADIO_PANEL_BUTTONS: [Bit<RadioPanelInputs, Button>; 2] = [
+    (RadioPanelInputs::swap1, Button::Swap(RadioRow::Upper)),
+    (RadioPanelInputs::swap2, Button::Swap(RadioRow::Lower))
+];
+
+const FIP_BUTTONS: [Bit<FlightInstrumentPanelInputs, FipButton>; 8] = [
+    (FlightInstrumentPanelInputs::s1, FipButton::S1),
+    (FlightInstrumentPanelInputs::s2, FipButton::S2),
+    (FlightInstrumentPanelInputs::s3, FipButton::S3),
+    (FlightInstrumentPanelInputs::s4, FipButton::S4),
+    (FlightInstrumentPanelInputs::s5, FipButton::S5),
+    (FlightInstrumentPanelInputs::s6, FipButton::S6),
+    (FlightInstrumentPanelInputs::up, FipButton::Up),
+    (FlightInstrumentPanelInputs::down, FipButton::Down)
+];
+
+/// Turns the snapshots of `Flightpanels::inputs()` into events for what changed.
+///
+/// The first report of a panel is compared to everything off, so switches that
+/// are already on show up as toggled. Knob detents are counted by [`Encoders`].
+#[derive(Default)]
+pub struct EventDecoder {
+    previous: HashMap<DeviceId, InputData>,
+    encoders: Encoders
+}
+
+impl EventDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn set_acceleration(&mut self, knob: Knob, curve: Acceleration) {
+        self.encoders.set_acceleration(knob, curve);
+    }
+
+    /// Events for the changes since the last snapshot of the same panel.
+    pub fn decode(&mut self, input: &InputData) -> Vec<InputEvent> {
+        self.decode_at(input, Instant::now())
+    }
+
+    /// Like `decode` for a report received at `now`, e.g. when replaying a recording.
+    pub fn decode_at(&mut self, input: &InputData, now: Instant) -> Vec<InputEvent> {
+        let mut events = self.changes(input);
+        events.extend(self.encoders.decode_at(input, now));
+        events
+    }
+
+    fn changes(&mut self, input: &InputData) -> Vec<InputEvent> {
+        let device = input.device();
+        let previous = self.previous.get(device);
+        let events = match input {
+            InputData::SwitchInputData(_, new) => {
+                let old = match previous { Some(InputData::SwitchInputData(_, old)) => *old, _ => SwitchPanelInputs::new() };
+                let mut events = toggles(&old, new, &SWITCH_PANEL_SWITCHES);
+                if old.engine_selector() != new.engine_selector() {
+                    events.push(Event::SelectorChanged(Selector::Engine(new.engine_selector())));
+                }
+                events
+            },
+            InputData::MultiInputData(_, new) => {
+                let old = match previous { Some(InputData::MultiInputData(_, old)) => *old, _ => MultiPanelInputs::new() };
+                let mut events = toggles(&old, new, &MULTI_PANEL_SWITCHES);
+                events.extend(presses(&old, new, &MULTI_PANEL_BUTTONS));
+                if old.selector() != new.selector() {
+                    events.push(Event::SelectorChanged(Selector::Multi(new.selector())));
+                }
+                events
+            },
+            InputData::RadioInputData(_, new) => {
+                let old = match previous { Some(InputData::RadioInputData(_, old)) => *old, _ => RadioPanelInputs::new() };
+                let mut events = Vec::new();
+                if old.selector1() != new.selector1() {
+                    events.push(Event::SelectorChanged(Selector::Radio(RadioRow::Upper, new.selector1())));
+                }
+                if old.selector2() != new.selector2() {
+                    events.push(Event::SelectorChanged(Selector::Radio(RadioRow::Lower, new.selector2())));
+                }
+                events.extend(presses(&old, new, &RADIO_PANEL_BUTTONS));
+                events
+            },
+            InputData::FIPInputData(_, new) => {
+                let old = match previous { Some(InputData::FIPInputData(_, old)) => *old, _ => FlightInstrumentPanelInputs::new() };
+                presses(&old, new, &FIP_BUTTONS)
+            },
+            InputData::DeviceConnected(_) | InputData::DeviceDisconnected(_) => {
+                // a panel that comes back starts from scratch
+                self.previous.remove(device);
+                return Vec::new();
+            },
+            InputData::DeviceError(..) => return Vec::new()
+        };
+        self.previous.insert(device.clone(), input.clone());
+        events.into_iter().map(|event| InputEvent { device: device.clone(), event }).collect()
+    }
+}
+
+fn toggles<S>(old: &S, new: &S, table: &[Bit<S, Switch>]) -> Vec<Event> {
+    table.iter()
+        .filter(|(get, _)| get(old) != get(new))
+        .map(|(get, switch)| Event::SwitchToggled { switch: *switch, on: get(new) })
+        .collect()
+}
+
+fn presses<S, B: Into<Button> + Copy>(old: &S, new: &S, table: &[Bit<S, B>]) -> Vec<Event> {
+    table.iter()
+        .filter(|(get, _)| get(old) != get(new))
+        .map(|(get, button)| if get(new) { Event::ButtonPressed((*button).into()) } else { Event::ButtonReleased((*button).into()) })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PanelKind;
+
+    fn events(decoder: &mut EventDecoder, input: InputData) -> Vec<Event> {
+        decoder.decode(&input).into_iter().map(|event| event.event).collect()
+    }
+
+    #[test]
+    fn switch_panel() {
+        let id = DeviceId::new(PanelKind::Switch, "1");
+        let mut decoder = EventDecoder::new();
+        let inputs = SwitchPanelInputs::new().with_battery(true).with_engine_selector(EngineSelection::OFF);
+        assert_eq!(events(&mut decoder, InputData::SwitchInputData(id.clone(), inputs)), vec![
+            Event::SwitchToggled { switch: Switch::Battery, on: true },
+            Event::SelectorChanged(Selector::Engine(EngineSelection::OFF))
+        ]);
+        assert_eq!(events(&mut decoder, InputData::SwitchInputData(id.clone(), inputs)), vec![]);
+        let inputs = inputs.with_battery(false).with_beacon_lights(true);
+        assert_eq!(events(&mut decoder, InputData::SwitchInputData(id, inputs)), vec![
+            Event::SwitchToggled { switch: Switch::Battery, on: false },
+            Event::SwitchToggled { switch: Switch::BeaconLights, on: true }
+        ]);
+    }
+
+    #[test]
+    fn buttons_and_selectors() {
+        let multi = DeviceId::new(PanelKind::Multi, "1");
+        let radio = DeviceId::new(PanelKind::Radio, "2");
+        let mut decoder = EventDecoder::new();
+        let inputs = MultiPanelInputs::new().with_ap(true);
+        assert_eq!(events(&mut decoder, InputData::MultiInputData(multi.clone(), inputs)), vec![Event::ButtonPressed(MultiButton::Ap.into())]);
+        assert_eq!(events(&mut decoder, InputData::MultiInputData(multi, inputs.with_ap(false))), vec![Event::ButtonReleased(MultiButton::Ap.into())]);
+
+        let inputs = RadioPanelInputs::new().with_selector1(ComSelection::COM1).with_selector2(ComSelection::NAV1);
+        events(&mut decoder, InputData::RadioInputData(radio.clone(), inputs));
+        let inputs = inputs.with_selector2(ComSelection::ADF).with_swap2(true);
+        assert_eq!(events(&mut decoder, InputData::RadioInputData(radio.clone(), inputs)), vec![
+            Event::SelectorChanged(Selector::Radio(RadioRow::Lower, ComSelection::ADF)),
+            Event::ButtonPressed(Button::Swap(RadioRow::Lower))
+        ]);
+
+        // after reconnecting the panel is compared to everything off again
+        events(&mut decoder, InputData::DeviceConnected(radio.clone()));
+        assert_eq!(events(&mut decoder, InputData::RadioInputData(radio, inputs)).len(), 3);
+    }
+}
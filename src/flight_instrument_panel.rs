@@ -1,34 +1,133 @@
 use bitfield_struct::bitfield;
-use hidapi::HidApi;
+use hidapi::{DeviceInfo, HidApi};
 use std::sync::mpsc::{Sender, Receiver};
-use std::thread;
+use crate::direct_output::{self, DirectOutputDevice};
+use crate::PanelKind;
+use crate::device::{self, Panel, PanelHandle};
+use crate::error::Result;
+#[cfg(feature = "experimental-fip-images")]
+use crate::error::Error;
+use crate::transport::{HidTransport, Transport};
 
-const ID: (u16, u16) = (0x06A3, 0xA2AE);
+pub(crate) const ID: (u16, u16) = (0x06A3, 0xA2AE);
 
+/// Output state of a FIP: LEDs, pages and the last frame shown on each page.
+#[derive(Default)]
 pub struct FlightInstrumentPanel {
+    leds: [bool; 9],
+    // what the FIP shows, None until sent, so a (re)connected panel gets the whole state
+    sent_leds: [Option<bool>; 9],
+    #[cfg(feature = "experimental-fip-images")]
+    pages: Pages
 }
 
 impl FlightInstrumentPanel {
-    pub fn receive(api: &HidApi, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> Result<&'static str, &'static str> {
-        if let Ok(device) = api.open(ID.0, ID.1) {
-            thread::spawn(move || {
-                let mut input_buffer = [0u8; 2];
-                loop {
-                    match device.read_timeout(&mut input_buffer, 250) {
-                        Ok(_) => {
-                            tx.send(crate::InputData::FIPInputData(
-                                FlightInstrumentPanelInputs::from(u16::from_le_bytes(input_buffer[0..2].try_into().expect("incorrect input length")))
-                            )).expect("could not send");
-                        },
-                        Err(_e) => ()
-                    }
+    /// See [`PanelHandle`].
+    pub fn receive(api: &HidApi, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> Result<PanelHandle> {
+        let (id, device) = device::open_first::<Self>(api)?;
+        Ok(device::spawn::<Self, _>(id, device, tx, rx))
+    }
+
+    /// See [`PanelHandle`].
+    ///
+    /// Buttons are read as input reports, DirectOutput requests are sent with `write`.
+    pub fn receive_from<T: Transport + 'static>(device: T, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> PanelHandle {
+        device::spawn::<Self, T>(crate::DeviceId::new(PanelKind::FlightInstrument, ""), device, tx, rx)
+    }
+}
+
+impl Panel for FlightInstrumentPanel {
+    type Command = OutputCommands;
+    const KIND: PanelKind = PanelKind::FlightInstrument;
+    const ID: (u16, u16) = ID;
+
+    fn decode(device: &crate::DeviceId, report: [u8; 4]) -> crate::InputData {
+        crate::InputData::FIPInputData(device.clone(), FlightInstrumentPanelInputs::from(u16::from_le_bytes([report[0], report[1]])))
+    }
+
+    fn apply(&mut self, command: OutputCommands) -> Result<()> {
+        match command {
+            OutputCommands::SetLed(led, on) => self.leds[led as usize] = on,
+            OutputCommands::SetSoftButtonLeds(states) => {
+                for (led, on) in FipLed::SOFT_BUTTONS.iter().zip(states) {
+                    self.leds[*led as usize] = on;
+                }
+            },
+            OutputCommands::SetAllLeds(on) => {
+                for led in FipLed::ALL {
+                    self.leds[led as usize] = on;
                 }
-            });
-            return Ok("super")
+            },
+            #[cfg(feature = "experimental-fip-images")]
+            OutputCommands::AddPage(page, activate) => self.pages.add(page, activate),
+            #[cfg(feature = "experimental-fip-images")]
+            OutputCommands::RemovePage(page) => self.pages.remove(page),
+            #[cfg(feature = "experimental-fip-images")]
+            OutputCommands::SetActivePage(page) => self.pages.active = page,
+            #[cfg(feature = "experimental-fip-images")]
+            OutputCommands::SetImage(page, frame) => self.pages.set_image(page, frame)
         }
-        else {
-            return Err("Could not open FIP device")
+        Ok(())
+    }
+
+    fn flush(&mut self, device: &dyn Transport) -> Result<()> {
+        // only the latest state is kept, what a failed write left out goes out with the next flush
+        #[cfg(feature = "experimental-fip-images")]
+        self.pages.flush(device)?;
+        for led in FipLed::ALL {
+            let on = self.leds[led as usize];
+            if self.sent_leds[led as usize] != Some(on) {
+                device.write(&direct_output::set_led_request(led as u8, on))?;
+                self.sent_leds[led as usize] = Some(on);
+            }
         }
+        Ok(())
+    }
+
+    fn invalidate(&mut self) {
+        self.sent_leds = [None; 9];
+        #[cfg(feature = "experimental-fip-images")]
+        self.pages.invalidate();
+    }
+
+    fn dark(&mut self) {
+        self.leds = [false; 9];
+        #[cfg(feature = "experimental-fip-images")]
+        self.pages.dark();
+    }
+
+    fn open(api: &HidApi, info: &DeviceInfo) -> Result<Box<dyn Transport>> {
+        let device = HidTransport::open(api, info.path()).map_err(|error| crate::error::open_error(api, PanelKind::FlightInstrument, ID, error))?;
+        // without the bulk channel the buttons still work, every command reports why it failed
+        let output = DirectOutputDevice::open(ID.0, ID.1, info.serial_number().filter(|serial| !serial.is_empty()));
+        Ok(Box::new(FipDevice { hid: device, output }))
+    }
+}
+
+/// The FIP reports its buttons over HID and takes everything else over the DirectOutput bulk channel.
+struct FipDevice {
+    hid: HidTransport,
+    output: Result<DirectOutputDevice>
+}
+
+impl Transport for FipDevice {
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> Result<usize> {
+        self.hid.read_timeout(buf, timeout)
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        self.hid.send_feature_report(data)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        match &self.output {
+            Ok(output) => output.write(data),
+            Err(error) => Err(error.clone())
+        }
+    }
+
+    fn get_input_report(&self, buf: &mut [u8]) -> Result<usize> {
+        self.hid.get_input_report(buf)
     }
 }
 
@@ -52,14 +151,214 @@ pub struct FlightInstrumentPanelInputs {
     _pad: u16
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum FipLed {
+    S1 = 1,
+    S2 = 2,
+    S3 = 3,
+    S4 = 4,
+    S5 = 5,
+    S6 = 6,
+    UpArrow = 7,
+    DownArrow = 8
+}
+
+impl FipLed {
+    pub const SOFT_BUTTONS: [FipLed; 6] = [FipLed::S1, FipLed::S2, FipLed::S3, FipLed::S4, FipLed::S5, FipLed::S6];
+    pub const ALL: [FipLed; 8] = [FipLed::S1, FipLed::S2, FipLed::S3, FipLed::S4, FipLed::S5, FipLed::S6, FipLed::UpArrow, FipLed::DownArrow];
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum OutputCommands {
+    SetLed(FipLed, bool),
+    /// States of S1 to S6, e.g. to light the button of the active page.
+    SetSoftButtonLeds([bool; 6]),
+    SetAllLeds(bool),
+    /// Adds a page, optionally making it the active one.
+    #[cfg(feature = "experimental-fip-images")]
+    AddPage(u32, bool),
+    #[cfg(feature = "experimental-fip-images")]
+    RemovePage(u32),
+    #[cfg(feature = "experimental-fip-images")]
+    SetActivePage(u32),
+    /// Shows the frame on a page, the page is added first if needed.
+    #[cfg(feature = "experimental-fip-images")]
+    SetImage(u32, Frame)
+}
+
+/*
+frame output is not delivered: frames go out with page and image requests whose codes are guessed
+and have not been confirmed with a USB capture (see direct_output.rs), so they only exist with the
+experimental-fip-images feature
+*/
+
+#[cfg(feature = "experimental-fip-images")]
+pub const SCREEN_WIDTH: usize = 320;
+#[cfg(feature = "experimental-fip-images")]
+pub const SCREEN_HEIGHT: usize = 240;
+#[cfg(feature = "experimental-fip-images")]
+const FRAME_BYTES: usize = SCREEN_WIDTH * SCREEN_HEIGHT * 3;
 
+/// Content of the 320x240 colour display, stored as top-down RGB rows.
+#[cfg(feature = "experimental-fip-images")]
+#[derive(Clone, PartialEq, Eq)]
+pub struct Frame {
+    rgb: Vec<u8>
 }
 
+#[cfg(feature = "experimental-fip-images")]
+impl Default for Frame {
+    fn default() -> Self {
+        Frame { rgb: vec![0; FRAME_BYTES] }
+    }
+}
+
+#[cfg(feature = "experimental-fip-images")]
+impl std::fmt::Debug for Frame {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Frame").field("width", &SCREEN_WIDTH).field("height", &SCREEN_HEIGHT).finish()
+    }
+}
 
+#[cfg(feature = "experimental-fip-images")]
+impl Frame {
+    /// Takes 320x240 pixels as top-down RGB rows.
+    pub fn from_rgb(rgb: Vec<u8>) -> Result<Self> {
+        if rgb.len() != FRAME_BYTES {
+            return Err(Error::InvalidFrameSize(rgb.len()))
+        }
+        Ok(Frame { rgb })
+    }
 
+    #[cfg(feature = "image")]
+    pub fn from_image(image: &image::DynamicImage) -> Result<Self> {
+        let rgb = image.to_rgb8();
+        if rgb.width() as usize != SCREEN_WIDTH || rgb.height() as usize != SCREEN_HEIGHT {
+            return Err(Error::InvalidFrameSize(rgb.len()))
+        }
+        Frame::from_rgb(rgb.into_raw())
+    }
 
+    pub fn as_rgb(&self) -> &[u8] {
+        &self.rgb
+    }
 
+    pub fn set_pixel(&mut self, x: usize, y: usize, rgb: [u8; 3]) {
+        if x < SCREEN_WIDTH && y < SCREEN_HEIGHT {
+            let offset = (y * SCREEN_WIDTH + x) * 3;
+            self.rgb[offset..offset + 3].copy_from_slice(&rgb);
+        }
+    }
+
+    /// BGR pixels with the bottom row first, as the FIP expects them.
+    pub fn to_device_layout(&self) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::with_capacity(FRAME_BYTES);
+        for row in self.rgb.chunks(SCREEN_WIDTH * 3).rev() {
+            for pixel in row.chunks(3) {
+                data.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+            }
+        }
+        data
+    }
+}
+
+/// Pages of a FIP and what of them has been sent.
+#[cfg(feature = "experimental-fip-images")]
+struct Pages {
+    pages: Vec<u32>,
+    active: u32,
+    // the last frame of each page
+    images: Vec<(u32, Frame)>,
+    // pages the FIP has and the one it shows, None until sent
+    sent_pages: Option<Vec<u32>>,
+    sent_active: Option<u32>,
+    // pages whose frame has not been sent
+    unsent_images: Vec<u32>
+}
+
+#[cfg(feature = "experimental-fip-images")]
+impl Default for Pages {
+    fn default() -> Self {
+        Pages {
+            pages: vec![direct_output::DEFAULT_PAGE],
+            active: direct_output::DEFAULT_PAGE,
+            images: Vec::new(),
+            sent_pages: None,
+            sent_active: None,
+            unsent_images: Vec::new()
+        }
+    }
+}
+
+#[cfg(feature = "experimental-fip-images")]
+impl Pages {
+    fn add(&mut self, page: u32, activate: bool) {
+        if !self.pages.contains(&page) {
+            self.pages.push(page);
+        }
+        if activate {
+            self.active = page;
+        }
+    }
+
+    fn remove(&mut self, page: u32) {
+        self.pages.retain(|p| *p != page);
+        self.images.retain(|(p, _)| *p != page);
+        self.unsent_images.retain(|p| *p != page);
+    }
+
+    /// Shows `frame` on `page`, adding the page if needed.
+    fn set_image(&mut self, page: u32, frame: Frame) {
+        self.add(page, false);
+        self.images.retain(|(p, _)| *p != page);
+        self.images.push((page, frame));
+        if !self.unsent_images.contains(&page) {
+            self.unsent_images.push(page);
+        }
+    }
+
+    fn flush(&mut self, device: &dyn Transport) -> Result<()> {
+        let sent = self.sent_pages.get_or_insert_with(Vec::new);
+        while let Some(index) = sent.iter().position(|page| !self.pages.contains(page)) {
+            device.write(&direct_output::remove_page_request(sent[index]))?;
+            sent.remove(index);
+        }
+        for page in &self.pages {
+            if !sent.contains(page) {
+                let activate = *page == self.active;
+                device.write(&direct_output::add_page_request(*page, activate))?;
+                sent.push(*page);
+                if activate {
+                    self.sent_active = Some(*page);
+                }
+            }
+        }
+        if self.sent_active != Some(self.active) {
+            device.write(&direct_output::set_active_page_request(self.active))?;
+            self.sent_active = Some(self.active);
+        }
+        while let Some(&page) = self.unsent_images.first() {
+            if let Some((_, frame)) = self.images.iter().find(|(p, _)| *p == page) {
+                device.write(&direct_output::set_image_request(page, &frame.to_device_layout()))?;
+            }
+            self.unsent_images.remove(0);
+        }
+        Ok(())
+    }
+
+    fn invalidate(&mut self) {
+        self.sent_pages = None;
+        self.sent_active = None;
+        self.unsent_images = self.images.iter().map(|(page, _)| *page).collect();
+    }
+
+    fn dark(&mut self) {
+        for page in self.images.iter().map(|(page, _)| *page).collect::<Vec<_>>() {
+            self.set_image(page, Frame::default());
+        }
+    }
+}
 
 /*
 Wireshark recording of setting LEDS:
@@ -98,4 +397,76 @@ Leftover Capture Data: 000000000000000100000000000000000000000000000018000000030
 Leftover Capture Data: 0000000000000001000000000000000000000000000000180000000300000007000000000000000000000000   - set led 7 -> 0 (UP_ARROW)
 Leftover Capture Data: 0000000000000001000000000000000000000000000000180000000400000008000000000000000000000000   - set led 8 -> 0 (DOWN_ARROW)
 
-*/
\ No newline at end of file
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MockTransport;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[cfg(feature = "experimental-fip-images")]
+    #[test]
+    fn device_layout() {
+        let mut frame = Frame::default();
+        frame.set_pixel(0, 0, [1, 2, 3]);
+        frame.set_pixel(SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1, [4, 5, 6]);
+        let data = frame.to_device_layout();
+        assert_eq!(data.len(), FRAME_BYTES);
+        assert_eq!(data[0..3], [0, 0, 0]);
+        assert_eq!(data[(SCREEN_WIDTH - 1) * 3..SCREEN_WIDTH * 3], [6, 5, 4]);
+        assert_eq!(data[FRAME_BYTES - SCREEN_WIDTH * 3..FRAME_BYTES - SCREEN_WIDTH * 3 + 3], [3, 2, 1]);
+        assert!(Frame::from_rgb(vec![0; 10]).is_err());
+    }
+
+    #[test]
+    fn failed_writes_keep_only_the_state() {
+        let mut panel = FlightInstrumentPanel::default();
+        let unplugged = MockTransport::new();
+        unplugged.disconnect();
+        for frame in 0..100 {
+            panel.apply(OutputCommands::SetLed(FipLed::S1, frame % 2 == 1)).unwrap();
+            #[cfg(feature = "experimental-fip-images")]
+            panel.apply(OutputCommands::SetImage(2, Frame::default())).unwrap();
+            assert!(panel.flush(&unplugged).is_err());
+        }
+
+        let mock = MockTransport::new();
+        panel.flush(&mock).unwrap();
+        #[cfg(feature = "experimental-fip-images")]
+        {
+            assert_eq!(mock.next_write(Duration::ZERO), Some(direct_output::add_page_request(direct_output::DEFAULT_PAGE, true)));
+            assert_eq!(mock.next_write(Duration::ZERO), Some(direct_output::add_page_request(2, false)));
+            assert_eq!(mock.next_write(Duration::ZERO), Some(direct_output::set_image_request(2, &Frame::default().to_device_layout())));
+        }
+        for led in FipLed::ALL {
+            assert_eq!(mock.next_write(Duration::ZERO), Some(direct_output::set_led_request(led as u8, led == FipLed::S1)));
+        }
+        assert_eq!(mock.next_write(Duration::ZERO), None);
+        panel.flush(&mock).unwrap();
+        assert_eq!(mock.next_write(Duration::ZERO), None);
+    }
+
+    #[test]
+    fn mock_device() {
+        let mock = MockTransport::new();
+        let (tx, rx) = mpsc::channel();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        FlightInstrumentPanel::receive_from(mock.clone(), tx, cmd_rx);
+        #[cfg(feature = "experimental-fip-images")]
+        assert_eq!(mock.next_write(Duration::from_secs(2)), Some(direct_output::add_page_request(direct_output::DEFAULT_PAGE, true)));
+        for led in FipLed::ALL {
+            assert_eq!(mock.next_write(Duration::from_secs(2)), Some(direct_output::set_led_request(led as u8, false)));
+        }
+
+        mock.push_input(&u16::from(FlightInstrumentPanelInputs::new().with_s3(true)).to_le_bytes());
+        match rx.recv_timeout(Duration::from_secs(2)) {
+            Ok(crate::InputData::FIPInputData(_, data)) => assert!(data.s3()),
+            _ => panic!("no FIP input")
+        }
+
+        cmd_tx.send(OutputCommands::SetLed(FipLed::S3, true)).unwrap();
+        assert_eq!(mock.next_write(Duration::from_secs(2)), Some(direct_output::set_led_request(3, true)));
+    }
+}
@@ -1,75 +1,405 @@
+use std::collections::HashMap;
+use std::fmt;
 use std::sync::mpsc::{Sender, Receiver};
-use std::sync::mpsc;
-use hidapi::HidApi;
-use switch_panel::EngineSelection;
+use std::sync::{mpsc, Arc, Mutex, MutexGuard};
+use std::thread;
 
-mod multi_panel;
-mod radio_panel;
-mod switch_panel;
-mod flight_instrument_panel;
+pub mod error;
+pub mod multi_panel;
+pub mod radio_panel;
+pub mod switch_panel;
+pub mod flight_instrument_panel;
+pub mod direct_output;
+pub mod transport;
+pub mod events;
+pub mod encoders;
+pub mod discovery;
+pub mod recording;
+#[cfg(feature = "simulator")]
+pub mod simulator;
+pub mod radio_stack;
+pub mod transponder;
+pub mod dme;
+pub mod segments;
+#[cfg(feature = "tokio")]
+pub mod async_api;
+mod device;
 
-struct Flightpanels {
-    api: HidApi
+pub use error::{Error, Result};
+
+pub use multi_panel::{MultiPanelInputs, MultiPanelOutputs, MultiPanelOutputLeds, MultiPanelLed, MultiDisplay, SettingSelection};
+pub use multi_panel::OutputCommands as MultiPanelCommands;
+pub use radio_panel::{RadioPanelInputs, RadioPanelOutputs, RadioDisplay, RadioRow, ComSelection};
+pub use radio_panel::OutputCommands as RadioPanelCommands;
+pub use switch_panel::{SwitchPanelInputs, EngineSelection, GearLedsStates, LedColors};
+pub use switch_panel::OutputCommands as SwitchPanelCommands;
+pub use flight_instrument_panel::{FlightInstrumentPanelInputs, FipLed};
+#[cfg(feature = "experimental-fip-images")]
+pub use flight_instrument_panel::Frame;
+pub use flight_instrument_panel::OutputCommands as FlightInstrumentPanelCommands;
+pub use device::PanelHandle;
+pub use discovery::{discover, PanelInfo};
+pub use events::{EventDecoder, InputEvent, Event, Switch, Button, MultiButton, FipButton, Selector};
+pub use encoders::{Encoders, Knob, Acceleration};
+pub use recording::{Recorder, Recording};
+pub use radio_stack::RadioStack;
+pub use transponder::{Squawk, TransponderMode};
+pub use segments::{Align, NumberFormat};
+
+/// Everything needed to receive inputs and build outputs, for glob import.
+pub mod prelude {
+    pub use crate::{Flightpanels, InputData, DeviceId, PanelKind, Error, discover, PanelInfo};
+    pub use crate::{MultiPanelInputs, MultiPanelOutputs, MultiPanelOutputLeds, MultiPanelLed, MultiDisplay, SettingSelection, MultiPanelCommands};
+    pub use crate::{RadioPanelInputs, RadioPanelOutputs, RadioDisplay, RadioRow, ComSelection, RadioPanelCommands};
+    pub use crate::{SwitchPanelInputs, EngineSelection, GearLedsStates, LedColors, SwitchPanelCommands};
+    pub use crate::{FlightInstrumentPanelInputs, FipLed, FlightInstrumentPanelCommands};
+    #[cfg(feature = "experimental-fip-images")]
+    pub use crate::Frame;
+    pub use crate::{EventDecoder, InputEvent, Event, Switch, Button, MultiButton, FipButton, Selector};
+    pub use crate::{Encoders, Knob, Acceleration};
+}
+
+/// Handle to the connected flight panels.
+///
+/// `new()` starts one thread per panel model and a monitor thread that opens
+/// panels as they are plugged in, then returns immediately. Inputs of all panels
+/// arrive on a single channel, outputs are sent to each panel through its own
+/// typed sender. Commands sent while a panel is unplugged are kept and shown
+/// once it is connected again.
+///
+/// Several panels of the same model are told apart by their [`DeviceId`]. The
+/// plain accessors such as `radio_panel()` drive the first panel of a model,
+/// the `*_for` accessors address one by name or serial.
+pub struct Flightpanels {
+    rx: Receiver<InputData>,
+    multi_tx: Sender<multi_panel::OutputCommands>,
+    radio_tx: Sender<radio_panel::OutputCommands>,
+    switch_tx: Sender<switch_panel::OutputCommands>,
+    fip_tx: Sender<flight_instrument_panel::OutputCommands>,
+    registry: Arc<Mutex<device::Registry>>,
+    control: Arc<device::Control>,
+    monitor: Option<thread::JoinHandle<()>>
+}
+
+/// Identifies one panel among several of the same model.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct DeviceId {
+    pub kind: PanelKind,
+    /// Serial number of the panel, its USB path if it reports none.
+    pub serial: String,
+    /// Name given to the serial with [`Flightpanels::with_names`].
+    pub name: Option<String>
+}
+
+impl DeviceId {
+    pub fn new(kind: PanelKind, serial: impl Into<String>) -> Self {
+        DeviceId { kind, serial: serial.into(), name: None }
+    }
+
+    /// The name if one was given, the serial otherwise.
+    pub fn label(&self) -> &str {
+        self.name.as_deref().unwrap_or(&self.serial)
+    }
+}
+
+impl fmt::Display for DeviceId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.label().is_empty() {
+            write!(f, "{}", self.kind)
+        }
+        else {
+            write!(f, "{} {}", self.kind, self.label())
+        }
+    }
 }
 
+#[derive(Debug, Clone, PartialEq)]
 pub enum InputData {
-    RadioInputData(radio_panel::RadioPanelInputs),
-    MultiInputData(multi_panel::MultiPanelInputs),
-    SwitchInputData(switch_panel::SwitchPanelInputs),
-    FIPInputData(flight_instrument_panel::FlightInstrumentPanelInputs)
+    RadioInputData(DeviceId, radio_panel::RadioPanelInputs),
+    MultiInputData(DeviceId, multi_panel::MultiPanelInputs),
+    SwitchInputData(DeviceId, switch_panel::SwitchPanelInputs),
+    FIPInputData(DeviceId, flight_instrument_panel::FlightInstrumentPanelInputs),
+    /// A panel thread could not open, read or update its device.
+    DeviceError(DeviceId, Error),
+    /// A panel has been opened, its output state has been sent to it.
+    DeviceConnected(DeviceId),
+    /// A panel has been unplugged or stopped responding, after the `DeviceError` saying why.
+    DeviceDisconnected(DeviceId)
+}
+
+impl InputData {
+    /// The panel this event comes from.
+    pub fn device(&self) -> &DeviceId {
+        match self {
+            InputData::RadioInputData(id, _) | InputData::MultiInputData(id, _) | InputData::SwitchInputData(id, _)
+                | InputData::FIPInputData(id, _) | InputData::DeviceError(id, _) => id,
+            InputData::DeviceConnected(id) | InputData::DeviceDisconnected(id) => id
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PanelKind {
+    Multi,
+    Radio,
+    Switch,
+    FlightInstrument
+}
+
+impl PanelKind {
+    pub const ALL: [PanelKind; 4] = [PanelKind::Multi, PanelKind::Radio, PanelKind::Switch, PanelKind::FlightInstrument];
+
+    /// USB vendor and product id of the model.
+    pub fn usb_id(&self) -> (u16, u16) {
+        match self {
+            PanelKind::Multi => multi_panel::ID,
+            PanelKind::Radio => radio_panel::ID,
+            PanelKind::Switch => switch_panel::ID,
+            PanelKind::FlightInstrument => flight_instrument_panel::ID
+        }
+    }
+
+    pub fn from_usb_id(vendor_id: u16, product_id: u16) -> Option<PanelKind> {
+        PanelKind::ALL.into_iter().find(|kind| kind.usb_id() == (vendor_id, product_id))
+    }
+
+    /// Short name used in recordings and on the command line.
+    pub fn name(&self) -> &'static str {
+        match self {
+            PanelKind::Multi => "multi",
+            PanelKind::Radio => "radio",
+            PanelKind::Switch => "switch",
+            PanelKind::FlightInstrument => "fip"
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<PanelKind> {
+        PanelKind::ALL.into_iter().find(|kind| kind.name() == name)
+    }
+}
+
+impl fmt::Display for PanelKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PanelKind::Multi => write!(f, "multi panel"),
+            PanelKind::Radio => write!(f, "radio panel"),
+            PanelKind::Switch => write!(f, "switch panel"),
+            PanelKind::FlightInstrument => write!(f, "flight instrument panel")
+        }
+    }
 }
 
 impl Flightpanels {
-    fn new() -> Option<Self> {
-        if let Ok(api) = hidapi::HidApi::new() {
-            let (tx, rx): (Sender<InputData>, Receiver<InputData>) = mpsc::channel();
-            let (switch_tx, switch_rx): (Sender<switch_panel::OutputCommands>, Receiver<switch_panel::OutputCommands>) = mpsc::channel();
-            let (radio_tx, radio_rx): (Sender<radio_panel::OutputCommands>, Receiver<radio_panel::OutputCommands>) = mpsc::channel();
-            let (fip_tx, fip_rx): (Sender<flight_instrument_panel::OutputCommands>, Receiver<flight_instrument_panel::OutputCommands>) = mpsc::channel();
-
-            multi_panel::MultiPanel::receive(&api, tx.clone()).expect("could not create thread for multi panel");
-            radio_panel::RadioPanel::receive(&api, tx.clone(), radio_rx).expect("could not create thread for radio panel");
-            switch_panel::SwitchPanel::receive(&api, tx.clone(), switch_rx).expect("could not create thread for switch panel");
-            flight_instrument_panel::FlightInstrumentPanel::receive(&api, tx.clone(), fip_rx).expect("could not create thread for FIP");
-
-            let mut engsel: EngineSelection = EngineSelection::Invalid;
-
-            loop {
-                match rx.recv() {
-                    Ok(rec) => match rec {
-                        InputData::MultiInputData(data) => (),//println!("{:#?}", data),
-                        InputData::RadioInputData(data) => (),//println!("{:#?}", data),
-                        InputData::SwitchInputData(data) => {
-                            if engsel != data.engine_selector()
-                            {
-                                engsel = data.engine_selector();
-                                match data.engine_selector() {
-                                    switch_panel::EngineSelection::LEFT => {switch_tx.send(switch_panel::OutputCommands::SetLeftLedTo(switch_panel::LedColors::Green));},
-                                    switch_panel::EngineSelection::RIGHT => {switch_tx.send(switch_panel::OutputCommands::SetRightLedTo(switch_panel::LedColors::Green));},
-                                    switch_panel::EngineSelection::BOTH => {switch_tx.send(switch_panel::OutputCommands::SetUpLedTo(switch_panel::LedColors::Green));},
-                                    switch_panel::EngineSelection::OFF => {switch_tx.send(switch_panel::OutputCommands::SetAllLedsTo(switch_panel::LedColors::Off));},
-                                    switch_panel::EngineSelection::START => {switch_tx.send(switch_panel::OutputCommands::SetLeds((switch_panel::GearLedsStates::LEFT_GREEN | switch_panel::GearLedsStates::UP_YELLOW | switch_panel::GearLedsStates::RIGHT_RED).bits()));},
-                                    switch_panel::EngineSelection::Invalid => ()
-                                }
-                                println!("{:#?}", data)
-                            }
-                        },
-                        InputData::FIPInputData(data) => println!("{:#?}", data),
-                    },
-                    Err(e) => println!("Error {}", e)
-                }
-            }
+    /// Opens all panels that are currently connected and keeps watching for more.
+    ///
+    /// Every panel that is opened is reported as `InputData::DeviceConnected`, panels
+    /// that are present but cannot be opened as `InputData::DeviceError`.
+    /// Fails only if hidapi itself could not be initialized.
+    ///
+    /// With the `simulator` feature and `FLIGHTPANELS_SIMULATOR` set to the address of a
+    /// running `flightpanels-sim`, the simulated panels are driven instead of USB panels.
+    pub fn new() -> Result<Self> {
+        Self::with_names(HashMap::<String, String>::new())
+    }
+
+    /// Like `new()`, naming panels by serial number, e.g. `[("0000A1B2", "radio-left")]`.
+    ///
+    /// The names show up in every [`DeviceId`] and can be used with the `*_for` accessors.
+    pub fn with_names<I, S, N>(names: I) -> Result<Self>
+    where I: IntoIterator<Item = (S, N)>, S: Into<String>, N: Into<String> {
+        let api = hidapi::HidApi::new()?;
+        let names = names.into_iter().map(|(serial, name)| (serial.into(), name.into())).collect();
+        let (tx, rx): (Sender<InputData>, Receiver<InputData>) = mpsc::channel();
+        let control = device::Control::new();
+
+        let mut registry = device::Registry::new(api, names, tx, control.clone());
+        let multi_tx = registry.add::<multi_panel::MultiPanel>();
+        let radio_tx = registry.add::<radio_panel::RadioPanel>();
+        let switch_tx = registry.add::<switch_panel::SwitchPanel>();
+        let fip_tx = registry.add::<flight_instrument_panel::FlightInstrumentPanel>();
+        // panels plugged in already are open when new() returns
+        registry.scan();
+        let registry = Arc::new(Mutex::new(registry));
+        let monitor_registry = registry.clone();
+        let monitor_control = control.clone();
+        let monitor = thread::spawn(move || device::monitor(monitor_registry, monitor_control));
+
+        Ok(Flightpanels { rx, multi_tx, radio_tx, switch_tx, fip_tx, registry, control, monitor: Some(monitor) })
+    }
+
+    fn registry(&self) -> MutexGuard<'_, device::Registry> {
+        self.registry.lock().expect("registry poisoned")
+    }
+
+    /// Whether shutting down switches every LED off and blanks every display, off by default.
+    pub fn set_blank_on_shutdown(&self, blank: bool) {
+        self.control.set_blank(blank);
+    }
+
+    /// Stops all panel threads and waits for them, the same as dropping the handle.
+    pub fn shutdown(self) {
+        drop(self);
+    }
+
+    fn stop(&mut self) {
+        self.control.stop();
+        if let Some(monitor) = self.monitor.take() {
+            let _ = monitor.join();
         }
-        None
+        self.registry().join();
+    }
+
+    /// Writes all traffic of all panels to `recorder` from now on, `None` stops recording.
+    ///
+    /// ```no_run
+    /// # use flightpanels_rs::{Flightpanels, Recorder};
+    /// let panels = Flightpanels::new().unwrap();
+    /// panels.record(Some(Recorder::create("session.rec").unwrap()));
+    /// ```
+    pub fn record(&self, recorder: Option<Recorder>) {
+        self.control.set_recorder(recorder);
+    }
+
+    /// Whether a panel of this kind is currently opened.
+    pub fn is_connected(&self, kind: PanelKind) -> bool {
+        self.registry().is_connected(kind)
+    }
+
+    /// All panels currently opened.
+    pub fn devices(&self) -> Vec<DeviceId> {
+        self.registry().devices()
+    }
+
+    /// Latest inputs of a connected panel.
+    ///
+    /// Panels are asked for their inputs when they connect, so the positions of
+    /// switches are known before they are first moved where the panel supports it.
+    pub fn current_state(&self, device: &DeviceId) -> Option<InputData> {
+        self.control.state(device)
+    }
+
+    /// Latest inputs of all connected panels.
+    pub fn current_states(&self) -> Vec<InputData> {
+        self.control.all_states()
+    }
+
+    /// Latest inputs of a panel of this kind, the one with the lowest serial if there are several.
+    fn first_state<T>(&self, select: impl Fn(&InputData) -> Option<T>) -> Option<T> {
+        let mut states = self.current_states();
+        states.sort_by(|a, b| a.device().serial.cmp(&b.device().serial));
+        states.iter().find_map(select)
+    }
+
+    pub fn multi_panel_state(&self) -> Option<MultiPanelInputs> {
+        self.first_state(|input| match input { InputData::MultiInputData(_, data) => Some(*data), _ => None })
+    }
+
+    pub fn radio_panel_state(&self) -> Option<RadioPanelInputs> {
+        self.first_state(|input| match input { InputData::RadioInputData(_, data) => Some(*data), _ => None })
+    }
+
+    pub fn switch_panel_state(&self) -> Option<SwitchPanelInputs> {
+        self.first_state(|input| match input { InputData::SwitchInputData(_, data) => Some(*data), _ => None })
+    }
+
+    pub fn flight_instrument_panel_state(&self) -> Option<FlightInstrumentPanelInputs> {
+        self.first_state(|input| match input { InputData::FIPInputData(_, data) => Some(*data), _ => None })
+    }
+
+    /// Inputs and connection events of all panels.
+    pub fn inputs(&self) -> &Receiver<InputData> {
+        &self.rx
+    }
+
+    /// Moves the input channel out, for wrappers reading it on their own thread.
+    #[cfg(feature = "tokio")]
+    pub(crate) fn take_inputs(&mut self) -> Receiver<InputData> {
+        std::mem::replace(&mut self.rx, mpsc::channel().1)
+    }
+
+    pub fn multi_panel(&self) -> &Sender<MultiPanelCommands> {
+        &self.multi_tx
+    }
+
+    pub fn radio_panel(&self) -> &Sender<RadioPanelCommands> {
+        &self.radio_tx
+    }
+
+    pub fn switch_panel(&self) -> &Sender<SwitchPanelCommands> {
+        &self.switch_tx
+    }
+
+    pub fn flight_instrument_panel(&self) -> &Sender<FlightInstrumentPanelCommands> {
+        &self.fip_tx
+    }
+
+    /// Multi panel with the given name or serial, which need not be plugged in yet.
+    pub fn multi_panel_for(&self, device: &str) -> Sender<MultiPanelCommands> {
+        self.registry().sender::<multi_panel::MultiPanel>(device)
+    }
+
+    /// Radio panel with the given name or serial, which need not be plugged in yet.
+    pub fn radio_panel_for(&self, device: &str) -> Sender<RadioPanelCommands> {
+        self.registry().sender::<radio_panel::RadioPanel>(device)
+    }
+
+    /// Switch panel with the given name or serial, which need not be plugged in yet.
+    pub fn switch_panel_for(&self, device: &str) -> Sender<SwitchPanelCommands> {
+        self.registry().sender::<switch_panel::SwitchPanel>(device)
+    }
+
+    /// FIP with the given name or serial, which need not be plugged in yet.
+    pub fn flight_instrument_panel_for(&self, device: &str) -> Sender<FlightInstrumentPanelCommands> {
+        self.registry().sender::<flight_instrument_panel::FlightInstrumentPanel>(device)
+    }
+}
+
+impl Drop for Flightpanels {
+    fn drop(&mut self) {
+        self.stop();
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::switch_panel;
+    use crate::prelude::*;
 
     #[test]
     fn basic_test() {
-        crate::Flightpanels::new();
+        let _ = crate::Flightpanels::new();
+    }
+
+    #[test]
+    fn public_accessors() {
+        let radio = RadioPanelInputs::from(1 | (2 << 7) | (1 << 15));
+        assert_eq!(radio.selector1(), ComSelection::COM1);
+        assert_eq!(radio.selector2(), ComSelection::COM2);
+        assert!(radio.swap2());
+        assert!(!radio.swap1());
+
+        let multi = MultiPanelInputs::new().with_selector(SettingSelection::HDG).with_ap(true);
+        assert_eq!(multi.selector(), SettingSelection::HDG);
+        assert!(multi.ap());
+    }
+
+    #[test]
+    fn device_ids() {
+        let mut id = DeviceId::new(PanelKind::Radio, "0000A1B2");
+        assert_eq!(id.to_string(), "radio panel 0000A1B2");
+        id.name = Some("radio-left".to_string());
+        assert_eq!(id.label(), "radio-left");
+        assert_eq!(InputData::DeviceConnected(id.clone()).device(), &id);
+        assert_eq!(DeviceId::new(PanelKind::Switch, "").to_string(), "switch panel");
+    }
+
+    #[test]
+    fn usb_ids() {
+        assert_eq!(PanelKind::Radio.usb_id(), (0x06A3, 0x0D05));
+        for kind in PanelKind::ALL {
+            let (vendor_id, product_id) = kind.usb_id();
+            assert_eq!(PanelKind::from_usb_id(vendor_id, product_id), Some(kind));
+        }
+        assert_eq!(PanelKind::from_usb_id(0x06A3, 0x0001), None);
+        let _ = discover();
     }
 }
@@ -1,8 +1,10 @@
 use bitfield_struct::bitfield;
 use hidapi::HidApi;
-use std::sync::mpsc::Sender;
-use std::result::Result;
-use std::thread;
+use std::sync::mpsc::{Sender, Receiver};
+use crate::PanelKind;
+use crate::device::{self, Panel, PanelHandle};
+use crate::error::{Error, Result};
+use crate::transport::Transport;
 
 /*
 outputs: 13bytes
@@ -22,34 +24,65 @@ byte12: padding?
 */
 
 
-const ID: (u16, u16) = (0x06A3, 0x0D06);
+pub(crate) const ID: (u16, u16) = (0x06A3, 0x0D06);
 const DASH: u8 = 0xEE;
 const BLANK: u8 = 0x0A;
 
+/// Output state of a multi panel.
+#[derive(Default)]
 pub struct MultiPanel {
+    outputs: MultiPanelOutputs,
+    sent_report: Option<Vec<u8>>
 }
 
 impl MultiPanel {
-    pub fn receive(api: &HidApi, tx: Sender<crate::InputData>) -> Result<&'static str, &'static str> {
-        if let Ok(device) = api.open(ID.0, ID.1) {
-            thread::spawn(move || {
-                let mut input_buffer = [0u8; 4];
-                loop {
-                    match device.read(&mut input_buffer) {
-                        Ok(_) => {
-                            tx.send(crate::InputData::MultiInputData(
-                                MultiPanelInputs::from(u32::from_le_bytes(input_buffer[0..4].try_into().expect("incorrect input length")))
-                            )).expect("could not send");
-                        },
-                        Err(_e) => ()
-                    }
-                }
-            });
-            return Ok("super")
+    /// See [`PanelHandle`].
+    pub fn receive(api: &HidApi, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> Result<PanelHandle> {
+        let (id, device) = device::open_first::<Self>(api)?;
+        Ok(device::spawn::<Self, _>(id, device, tx, rx))
+    }
+
+    /// See [`PanelHandle`].
+    pub fn receive_from<T: Transport + 'static>(device: T, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> PanelHandle {
+        device::spawn::<Self, T>(crate::DeviceId::new(PanelKind::Multi, ""), device, tx, rx)
+    }
+}
+
+impl Panel for MultiPanel {
+    type Command = OutputCommands;
+    const KIND: PanelKind = PanelKind::Multi;
+    const ID: (u16, u16) = ID;
+
+    fn decode(device: &crate::DeviceId, report: [u8; 4]) -> crate::InputData {
+        crate::InputData::MultiInputData(device.clone(), MultiPanelInputs::from(u32::from_le_bytes(report)))
+    }
+
+    fn apply(&mut self, command: OutputCommands) -> Result<()> {
+        match command {
+            OutputCommands::SetUpperDisplay(value) => self.outputs.set_display(MultiDisplay::UpperDisplay, value),
+            OutputCommands::SetLowerDisplay(value) => self.outputs.set_display(MultiDisplay::LowerDisplay, value),
+            OutputCommands::SetUpperDigits(digits) => { self.outputs.upper_display = digits; Ok(()) },
+            OutputCommands::SetLowerDigits(digits) => { self.outputs.lower_display = digits; Ok(()) },
+            OutputCommands::SetLeds(leds) => { self.outputs.leds = leds; Ok(()) },
+            OutputCommands::SetLed(led, on) => { self.outputs.set_led(led, on); Ok(()) }
         }
-        else {
-            return Err("Could not open device")
+    }
+
+    fn flush(&mut self, device: &dyn Transport) -> Result<()> {
+        let report = self.outputs.as_bytes();
+        if self.sent_report.as_ref() != Some(&report) {
+            device.send_feature_report(&report)?;
+            self.sent_report = Some(report);
         }
+        Ok(())
+    }
+
+    fn invalidate(&mut self) {
+        self.sent_report = None;
+    }
+
+    fn dark(&mut self) {
+        self.outputs = MultiPanelOutputs::default();
     }
 }
 
@@ -57,39 +90,87 @@ impl MultiPanel {
 #[derive(PartialEq, Eq)]
 pub struct MultiPanelInputs {
     #[bits(5)]
-    selector: SettingSelection,
-    jog_inc: bool,
-    jog_dec: bool,
-    ap: bool,
-    hdg: bool,
-    nav: bool,
-    ias: bool,
-    alt: bool,
-    vs: bool,
-    apr: bool,
-    rev: bool,
-    auto_throttle: bool,
-    flaps_up: bool,
-    flaps_down: bool,
-    pitch_down: bool,
-    pitch_up: bool,
+    pub selector: SettingSelection,
+    pub jog_inc: bool,
+    pub jog_dec: bool,
+    pub ap: bool,
+    pub hdg: bool,
+    pub nav: bool,
+    pub ias: bool,
+    pub alt: bool,
+    pub vs: bool,
+    pub apr: bool,
+    pub rev: bool,
+    pub auto_throttle: bool,
+    pub flaps_up: bool,
+    pub flaps_down: bool,
+    pub pitch_down: bool,
+    pub pitch_up: bool,
     #[bits(12)]
     _pad: u32
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct MultiPanelOutputs {
     pub upper_display: [u8; 5],
     pub lower_display: [u8; 5],
     pub leds: MultiPanelOutputLeds,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum MultiDisplay {
     UpperDisplay,
     LowerDisplay
 }
 
+impl MultiDisplay {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MultiDisplay::UpperDisplay => "upper",
+            MultiDisplay::LowerDisplay => "lower"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::upper_case_acronyms)]
+pub enum MultiPanelLed {
+    AP,
+    HDG,
+    NAV,
+    IAS,
+    ALT,
+    VS,
+    APR,
+    REV
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputCommands {
+    /// Shows a number between -9999 and 99999 on the upper display.
+    SetUpperDisplay(i32),
+    /// Shows a number between -9999 and 99999 on the lower display.
+    SetLowerDisplay(i32),
+    /// Raw digit codes for the upper display, left to right.
+    SetUpperDigits([u8; 5]),
+    /// Raw digit codes for the lower display, left to right.
+    SetLowerDigits([u8; 5]),
+    SetLeds(MultiPanelOutputLeds),
+    SetLed(MultiPanelLed, bool)
+}
+
+impl Default for MultiPanelOutputs {
+    fn default() -> Self {
+        MultiPanelOutputs {
+            upper_display: [BLANK; 5],
+            lower_display: [BLANK; 5],
+            leds: MultiPanelOutputLeds::new()
+        }
+    }
+}
+
 impl MultiPanelOutputs {
-    pub fn as_bytes(self) -> Vec<u8> {
+    pub fn as_bytes(&self) -> Vec<u8> {
         let mut data: Vec<u8> = Vec::with_capacity(13);
         data.push(0);   // hid report no.
         data.extend_from_slice(&self.upper_display[0..]);
@@ -99,12 +180,12 @@ impl MultiPanelOutputs {
         data
     }
 
-    pub fn set_display(&mut self, display: MultiDisplay, value: i32) -> Result<(), &'static str> {
+    pub fn set_display(&mut self, display: MultiDisplay, value: i32) -> Result<()> {
         let mut display_data: [u8; 5] = [0xff; 5];
         let mut val = value;
         let mut first_digit = true;
-        if val > 99999 || val < -9999 {
-            return Err("Value too long")
+        if !(-9999..=99999).contains(&val) {
+            return Err(Error::ValueOutOfRange { display: display.name(), value: value as f64 })
         }
         if val < 0 {
             display_data[0] = DASH;
@@ -118,7 +199,7 @@ impl MultiPanelOutputs {
         else {
             display_data[0] = BLANK;
         }
-        if(val >= 1000) {
+        if val >= 1000 {
             display_data[1] = (val / 1000).try_into().expect("could not convert to figure");
             val %= 1000;
             first_digit = false;
@@ -129,7 +210,7 @@ impl MultiPanelOutputs {
         else {
             display_data[1] = BLANK;
         }
-        if(val >= 100) {
+        if val >= 100 {
             display_data[2] = (val / 100).try_into().expect("could not convert to figure");
             val %= 100;
             first_digit = false;
@@ -140,10 +221,9 @@ impl MultiPanelOutputs {
         else {
             display_data[2] = BLANK;
         }
-        if(val >= 10) {
+        if val >= 10 {
             display_data[3] = (val / 10).try_into().expect("could not convert to figure");
             val %= 10;
-            first_digit = false;
         }
         else if !first_digit {
             display_data[3] = 0;
@@ -151,7 +231,7 @@ impl MultiPanelOutputs {
         else {
             display_data[3] = BLANK;
         }
-        display_data[3] = (val).try_into().expect("could not convert to figure");
+        display_data[4] = (val).try_into().expect("could not convert to figure");
 
         match display {
             MultiDisplay::UpperDisplay => { self.upper_display.swap_with_slice(&mut display_data); },
@@ -159,6 +239,19 @@ impl MultiPanelOutputs {
         }
         Ok(())
     }
+
+    pub fn set_led(&mut self, led: MultiPanelLed, on: bool) {
+        match led {
+            MultiPanelLed::AP => self.leds.set_ap(on),
+            MultiPanelLed::HDG => self.leds.set_hdg(on),
+            MultiPanelLed::NAV => self.leds.set_nav(on),
+            MultiPanelLed::IAS => self.leds.set_ias(on),
+            MultiPanelLed::ALT => self.leds.set_alt(on),
+            MultiPanelLed::VS => self.leds.set_vs(on),
+            MultiPanelLed::APR => self.leds.set_apr(on),
+            MultiPanelLed::REV => self.leds.set_rev(on)
+        }
+    }
 }
 
 #[bitfield(u8)]
@@ -174,8 +267,9 @@ pub struct MultiPanelOutputLeds {
     pub rev: bool
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum SettingSelection {
     Invalid = 0,
     ALT = 1,
@@ -185,9 +279,9 @@ pub enum SettingSelection {
     CRS = 16
 }
 
-impl Into<u32> for SettingSelection {
-    fn into(self) -> u32 {
-        self as u8 as u32
+impl From<SettingSelection> for u32 {
+    fn from(value: SettingSelection) -> Self {
+        value as u8 as u32
     }
 }
 
@@ -202,4 +296,43 @@ impl From<u32> for SettingSelection {
             16..=u32::MAX => SettingSelection::CRS
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::tests::mock_panel;
+    use std::time::Duration;
+
+    #[test]
+    fn display_values() {
+        let mut outputs = MultiPanelOutputs::default();
+        outputs.set_display(MultiDisplay::UpperDisplay, 12000).unwrap();
+        assert_eq!(outputs.upper_display, [1, 2, 0, 0, 0]);
+        outputs.set_display(MultiDisplay::LowerDisplay, 5).unwrap();
+        assert_eq!(outputs.lower_display, [BLANK, BLANK, BLANK, BLANK, 5]);
+        outputs.set_display(MultiDisplay::LowerDisplay, -500).unwrap();
+        assert_eq!(outputs.lower_display, [DASH, BLANK, 5, 0, 0]);
+        assert!(outputs.set_display(MultiDisplay::UpperDisplay, 100000).is_err());
+    }
+
+    #[test]
+    fn report_layout() {
+        let mut outputs = MultiPanelOutputs::default();
+        outputs.set_led(MultiPanelLed::AP, true);
+        outputs.set_led(MultiPanelLed::REV, true);
+        let report = outputs.as_bytes();
+        assert_eq!(report.len(), 13);
+        assert_eq!(report[0], 0);
+        assert_eq!(report[11], 0b1000_0001);
+    }
+
+    #[test]
+    fn mock_device() {
+        let inputs = MultiPanelInputs::new().with_selector(SettingSelection::ALT).with_hdg(true);
+        let (mock, cmd_tx, _inputs) = mock_panel(MultiPanel::receive_from, PanelKind::Multi, MultiPanelOutputs::default().as_bytes(), inputs.into(), |id| crate::InputData::MultiInputData(id, inputs));
+        cmd_tx.send(OutputCommands::SetLed(MultiPanelLed::HDG, true)).unwrap();
+        let report = mock.next_feature_report(Duration::from_secs(2)).expect("no report sent");
+        assert_eq!(report[11], 0b0000_0010);
+    }
+}
@@ -1,9 +1,11 @@
 use bitfield_struct::bitfield;
 use hidapi::HidApi;
 use std::sync::mpsc::{Sender, Receiver};
-use std::result::Result;
-use std::thread;
-use std::time::Duration;
+use crate::PanelKind;
+use crate::device::{self, Panel, PanelHandle};
+use crate::error::{Error, Result};
+use crate::segments::{self, Align, NumberFormat};
+use crate::transport::Transport;
 
 /*
 outputs: 23bytes
@@ -17,46 +19,67 @@ bytes 21-22: padding?
 
 
 
-const ID: (u16, u16) = (0x06A3, 0x0D05);
+pub(crate) const ID: (u16, u16) = (0x06A3, 0x0D05);
 
+/// Output state of a radio panel.
+#[derive(Default)]
 pub struct RadioPanel {
+    frequencies: RadioPanelOutputs,
+    // None until the first report went out, so a (re)connected panel starts blank
+    sent_report: Option<Vec<u8>>
 }
 
 impl RadioPanel {
-    pub fn receive(api: &HidApi, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> Result<&'static str, &'static str> {
-        if let Ok(device) = api.open(ID.0, ID.1) {
-            let mut frequencies  = RadioPanelOutputs{
-                upper_active_display: [0xff; 5],
-                upper_standby_display: [0xff; 5],
-                lower_active_display: [0xff; 5],
-                lower_standby_display: [0xff; 5]
-            };
-            thread::spawn(move || {
-                let mut input_buffer = [0u8; 4];
-                loop {
-                    match device.read_timeout(&mut input_buffer, 250) {
-                        Ok(_) => {
-                            tx.send(crate::InputData::RadioInputData(
-                                RadioPanelInputs::from(u32::from_le_bytes(input_buffer[0..4].try_into().expect("incorrect input length")))
-                            )).expect("could not send");
-                        },
-                        Err(_e) => ()
-                    }
-                    if let Ok(command) = rx.recv_timeout(Duration::from_millis(10)) {
-                        match command {
-                            OutputCommands::SetUpperActiveFrequency(freq) => frequencies.set_display(RadioDisplay::UpperActive, freq).expect("could not set frequency"),
-                            OutputCommands::SetUpperStandbyFrequency(freq) => frequencies.set_display(RadioDisplay::UpperStandby, freq).expect("could not set frequency"),
-                            OutputCommands::SetLowerActiveFrequency(freq) => frequencies.set_display(RadioDisplay::LowerActive, freq).expect("could not set frequency"),
-                            OutputCommands::SetLowerStandbyFrequency(freq) => frequencies.set_display(RadioDisplay::LowerStandby, freq).expect("could not set frequency"),
-                        }
-                    }
-                }
-            });
-            return Ok("super")
+    /// See [`PanelHandle`].
+    pub fn receive(api: &HidApi, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> Result<PanelHandle> {
+        let (id, device) = device::open_first::<Self>(api)?;
+        Ok(device::spawn::<Self, _>(id, device, tx, rx))
+    }
+
+    /// See [`PanelHandle`].
+    pub fn receive_from<T: Transport + 'static>(device: T, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> PanelHandle {
+        device::spawn::<Self, T>(crate::DeviceId::new(PanelKind::Radio, ""), device, tx, rx)
+    }
+}
+
+impl Panel for RadioPanel {
+    type Command = OutputCommands;
+    const KIND: PanelKind = PanelKind::Radio;
+    const ID: (u16, u16) = ID;
+
+    fn decode(device: &crate::DeviceId, report: [u8; 4]) -> crate::InputData {
+        crate::InputData::RadioInputData(device.clone(), RadioPanelInputs::from(u32::from_le_bytes(report)))
+    }
+
+    fn apply(&mut self, command: OutputCommands) -> Result<()> {
+        match command {
+            OutputCommands::SetUpperActiveFrequency(freq) => self.frequencies.set_display(RadioDisplay::UpperActive, freq),
+            OutputCommands::SetUpperStandbyFrequency(freq) => self.frequencies.set_display(RadioDisplay::UpperStandby, freq),
+            OutputCommands::SetLowerActiveFrequency(freq) => self.frequencies.set_display(RadioDisplay::LowerActive, freq),
+            OutputCommands::SetLowerStandbyFrequency(freq) => self.frequencies.set_display(RadioDisplay::LowerStandby, freq),
+            OutputCommands::SetDisplays(outputs) => {
+                self.frequencies = outputs;
+                Ok(())
+            }
         }
-        else {
-            return Err("Could not open device")
+    }
+
+    fn flush(&mut self, device: &dyn Transport) -> Result<()> {
+        // the full report is resent whenever any of the four displays changed
+        let report = self.frequencies.as_bytes();
+        if self.sent_report.as_ref() != Some(&report) {
+            device.send_feature_report(&report)?;
+            self.sent_report = Some(report);
         }
+        Ok(())
+    }
+
+    fn invalidate(&mut self) {
+        self.sent_report = None;
+    }
+
+    fn dark(&mut self) {
+        self.frequencies = RadioPanelOutputs::default();
     }
 }
 
@@ -64,23 +87,31 @@ impl RadioPanel {
 #[derive(PartialEq, Eq)]
 pub struct RadioPanelInputs {
     #[bits(7)]
-    selector1: ComSelection,
+    pub selector1: ComSelection,
     #[bits(7)]
-    selector2: ComSelection,
-    swap1: bool,
-    swap2: bool,
-    fine_inc1: bool,
-    fine_dec1: bool,
-    coarse_inc1: bool,
-    coarse_dec1: bool,
-    fine_inc2: bool,
-    fine_dec2: bool,
-    coarse_inc2: bool,
-    coarse_dec2: bool,
+    pub selector2: ComSelection,
+    pub swap1: bool,
+    pub swap2: bool,
+    pub fine_inc1: bool,
+    pub fine_dec1: bool,
+    pub coarse_inc1: bool,
+    pub coarse_dec1: bool,
+    pub fine_inc2: bool,
+    pub fine_dec2: bool,
+    pub coarse_inc2: bool,
+    pub coarse_dec2: bool,
     #[bits(8)]
     _pad: u32
 }
 
+/// One of the two radios of the panel, each with its selector, knob, swap button and displays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum RadioRow {
+    Upper,
+    Lower
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum RadioDisplay {
     UpperActive,
     UpperStandby,
@@ -88,6 +119,18 @@ pub enum RadioDisplay {
     LowerStandby
 }
 
+impl RadioDisplay {
+    pub fn name(&self) -> &'static str {
+        match self {
+            RadioDisplay::UpperActive => "upper active",
+            RadioDisplay::UpperStandby => "upper standby",
+            RadioDisplay::LowerActive => "lower active",
+            RadioDisplay::LowerStandby => "lower standby"
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub struct RadioPanelOutputs {
     pub upper_active_display: [u8; 5],
     pub upper_standby_display: [u8; 5],
@@ -95,8 +138,19 @@ pub struct RadioPanelOutputs {
     pub lower_standby_display: [u8; 5],
 }
 
+impl Default for RadioPanelOutputs {
+    fn default() -> Self {
+        RadioPanelOutputs {
+            upper_active_display: [0xff; 5],
+            upper_standby_display: [0xff; 5],
+            lower_active_display: [0xff; 5],
+            lower_standby_display: [0xff; 5]
+        }
+    }
+}
+
 impl RadioPanelOutputs {
-    pub fn as_bytes(self) -> Vec<u8> {
+    pub fn as_bytes(&self) -> Vec<u8> {
         let mut data: Vec<u8> = Vec::with_capacity(23);
         data.push(0);   // hid report no.
         data.extend_from_slice(&self.upper_active_display[0..]);
@@ -108,82 +162,75 @@ impl RadioPanelOutputs {
         data
     }
 
-    pub fn set_display(&mut self, display: RadioDisplay, value: f32) -> Result<(), &'static str>{
-        let mut display_data: [u8; 5] = [0xff; 5];
-        if value < 0.0 {
-            return Err("Displays cannot show negative values");
-        }
-        if value > 99999.0 {
-            return Err("Displays cannot show more than 5 figures")
+    /// Shows `value` with as many of 2, 1 or 0 decimals as fit, e.g. 118.25, 1234.5 or 12345, rounded.
+    pub fn set_display(&mut self, display: RadioDisplay, value: f32) -> Result<()>{
+        // displays cannot show negative values or more than 5 figures
+        if !(0.0..=99999.0).contains(&value) {
+            return Err(Error::ValueOutOfRange { display: display.name(), value: value as f64 });
         }
-        let mut first_digit = true;
-        let shift: u8;
-        let mut tmp_value: u32;
-        if value >= 10000.0 { shift = 0; tmp_value = value as u32;}
-        else if value >= 1000.0 { shift = 1; tmp_value = (value * 10.0) as u32;}
-        else { shift = 2; tmp_value = (value * 100.0) as u32;}
-        let mut figure: u8 = (tmp_value / 10000).try_into().expect("could not convert to figure");
-        if figure > 0 {
-            display_data[0] = figure;
-            first_digit = false;
-            tmp_value %= 10000;
-        }
-        figure = (tmp_value / 1000).try_into().expect("could not convert to figure");
-        if figure > 0 {
-            display_data[1] = figure;
-            first_digit = false;
-            tmp_value %= 1000;
-        }
-        else if !first_digit {
-            display_data[1] = 0;
-        }
-        figure = (tmp_value / 100).try_into().expect("could not convert to figure");
-        if figure > 0 {
-            if shift == 2 {
-                figure += 0xD0;
-            }
-            display_data[2] = figure;
-            first_digit = false;
-            tmp_value %= 100;
-        }
-        else {
-            if shift == 2 {
-                display_data[2] = 0xD0;
-            }
-            else if !first_digit {
-                display_data[2] = 0;
-            }
+        let codes = [2, 1, 0].into_iter()
+            .find_map(|decimals| segments::number(value as f64, NumberFormat::new(decimals)))
+            .ok_or(Error::ValueOutOfRange { display: display.name(), value: value as f64 })?;
+        *self.display_mut(display) = codes;
+        Ok(())
+    }
+
+    /// Shows `value` formatted as asked, negative values take a dash in front.
+    pub fn set_number(&mut self, display: RadioDisplay, value: f64, format: NumberFormat) -> Result<()> {
+        let codes = segments::number(value, format).ok_or(Error::ValueOutOfRange { display: display.name(), value })?;
+        *self.display_mut(display) = codes;
+        Ok(())
+    }
+
+    /// Shows text like " 7700", "118.25" or "-----", see `segments::text`.
+    pub fn set_text(&mut self, display: RadioDisplay, text: &str, align: Align) -> Result<()> {
+        let codes = segments::text(text, align).ok_or_else(|| Error::InvalidDisplayText { display: display.name(), text: text.to_string() })?;
+        *self.display_mut(display) = codes;
+        Ok(())
+    }
+
+    /// Sets the raw digit codes of a display, left to right.
+    pub fn set_codes(&mut self, display: RadioDisplay, codes: [u8; 5]) -> Result<()> {
+        if let Some(code) = codes.iter().find(|code| !segments::is_valid_code(**code)) {
+            return Err(Error::InvalidDisplayText { display: display.name(), text: format!("{:#04x}", code) });
         }
-        figure = (tmp_value / 10).try_into().expect("could not convert to figure");
-        if figure > 0 {
-            if shift == 1 {
-                figure += 0xD0;
-            }
-            display_data[3] = figure;
-            first_digit = false;
-            tmp_value %= 10;
+        *self.display_mut(display) = codes;
+        Ok(())
+    }
+
+    /// Sets the raw code of one digit, `position` 0 is the leftmost.
+    pub fn set_code(&mut self, display: RadioDisplay, position: usize, code: u8) -> Result<()> {
+        let mut codes = self.display(display);
+        match codes.get_mut(position) {
+            Some(digit) => *digit = code,
+            None => return Err(Error::InvalidDisplayText { display: display.name(), text: format!("digit {}", position) })
         }
-        else {
-            if shift == 1 {
-                display_data[3] = 0xD0;
-            }
-            else if !first_digit {
-                display_data[3] = 0;
-            }
+        self.set_codes(display, codes)
+    }
+
+    /// Digit codes of a display.
+    pub fn display(&self, display: RadioDisplay) -> [u8; 5] {
+        match display {
+            RadioDisplay::UpperActive => self.upper_active_display,
+            RadioDisplay::UpperStandby => self.upper_standby_display,
+            RadioDisplay::LowerActive => self.lower_active_display,
+            RadioDisplay::LowerStandby => self.lower_standby_display
         }
-        display_data[4] = (tmp_value % 10).try_into().expect("could not convert to figure");
+    }
+
+    fn display_mut(&mut self, display: RadioDisplay) -> &mut [u8; 5] {
         match display {
-            RadioDisplay::UpperActive => { self.upper_active_display.swap_with_slice(&mut display_data) },
-            RadioDisplay::UpperStandby => { self.upper_standby_display.swap_with_slice(&mut display_data) },
-            RadioDisplay::LowerActive => { self.lower_active_display.swap_with_slice(&mut display_data) },
-            RadioDisplay::LowerStandby => { self.lower_standby_display.swap_with_slice(&mut display_data) }
+            RadioDisplay::UpperActive => &mut self.upper_active_display,
+            RadioDisplay::UpperStandby => &mut self.upper_standby_display,
+            RadioDisplay::LowerActive => &mut self.lower_active_display,
+            RadioDisplay::LowerStandby => &mut self.lower_standby_display
         }
-        Ok(())
     }
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum ComSelection {
     Invalid = 0,
     COM1 = 1,
@@ -195,16 +242,20 @@ pub enum ComSelection {
     XPDR = 64
 }
 
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[allow(clippy::enum_variant_names)]
 pub enum OutputCommands {
     SetUpperActiveFrequency(f32),
     SetUpperStandbyFrequency(f32),
     SetLowerActiveFrequency(f32),
-    SetLowerStandbyFrequency(f32)
+    SetLowerStandbyFrequency(f32),
+    /// Replaces all four displays, e.g. with the outputs of a [`RadioStack`](crate::radio_stack::RadioStack).
+    SetDisplays(RadioPanelOutputs)
 }
 
-impl Into<u32> for ComSelection {
-    fn into(self) -> u32 {
-        self as u8 as u32
+impl From<ComSelection> for u32 {
+    fn from(value: ComSelection) -> Self {
+        value as u8 as u32
     }
 }
 
@@ -222,3 +273,108 @@ impl From<u32> for ComSelection {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MockTransport;
+    use crate::transport::tests::mock_panel;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn report_layout() {
+        let mut outputs = RadioPanelOutputs::default();
+        assert_eq!(outputs.as_bytes(), [&[0u8][..], &[0xff; 20], &[0, 0]].concat());
+        outputs.set_display(RadioDisplay::LowerStandby, 118.25).unwrap();
+        let report = outputs.as_bytes();
+        assert_eq!(report.len(), 23);
+        assert_eq!(report[16..21], [1, 1, 0xD8, 2, 5]);
+    }
+
+    #[test]
+    fn display_formats() {
+        let shown = |value: f32| {
+            let mut outputs = RadioPanelOutputs::default();
+            outputs.set_display(RadioDisplay::LowerStandby, value).map(|_| outputs.lower_standby_display)
+        };
+        assert_eq!(shown(0.0), Ok([0xff, 0xff, 0xD0, 0, 0]));
+        assert_eq!(shown(0.05), Ok([0xff, 0xff, 0xD0, 0, 5]));
+        assert_eq!(shown(0.5), Ok([0xff, 0xff, 0xD0, 5, 0]));
+        assert_eq!(shown(5.0), Ok([0xff, 0xff, 0xD5, 0, 0]));
+        assert_eq!(shown(10.05), Ok([0xff, 1, 0xD0, 0, 5]));
+        assert_eq!(shown(118.1), Ok([1, 1, 0xD8, 1, 0]));
+        assert_eq!(shown(118.275), Ok([1, 1, 0xD8, 2, 8]));
+        assert_eq!(shown(999.999), Ok([1, 0, 0, 0xD0, 0]));
+        assert_eq!(shown(1000.5), Ok([1, 0, 0, 0xD0, 5]));
+        assert_eq!(shown(1234.56), Ok([1, 2, 3, 0xD4, 6]));
+        assert_eq!(shown(9999.99), Ok([1, 0, 0, 0, 0]));
+        assert_eq!(shown(12345.0), Ok([1, 2, 3, 4, 5]));
+        assert_eq!(shown(99999.0), Ok([9, 9, 9, 9, 9]));
+        assert!(shown(99999.5).is_err());
+        assert!(shown(-0.1).is_err());
+        assert!(shown(f32::NAN).is_err());
+
+        let mut outputs = RadioPanelOutputs::default();
+        outputs.set_text(RadioDisplay::UpperActive, " 7700", Align::Right).unwrap();
+        outputs.set_text(RadioDisplay::UpperStandby, "-----", Align::Right).unwrap();
+        outputs.set_number(RadioDisplay::LowerActive, 42.0, NumberFormat { leading_zeros: true, ..NumberFormat::default() }).unwrap();
+        outputs.set_codes(RadioDisplay::LowerStandby, [0xD1, 2, 0xEE, 0xff, 0]).unwrap();
+        outputs.set_code(RadioDisplay::LowerStandby, 3, 0xD9).unwrap();
+        assert_eq!(outputs.as_bytes(), [0, 0xff, 7, 7, 0, 0, 0xEE, 0xEE, 0xEE, 0xEE, 0xEE, 0, 0, 0, 4, 2, 0xD1, 2, 0xEE, 0xD9, 0, 0, 0]);
+
+        assert_eq!(
+            outputs.set_text(RadioDisplay::UpperActive, "12.3a", Align::Left),
+            Err(Error::InvalidDisplayText { display: "upper active", text: "12.3a".to_string() })
+        );
+        assert_eq!(
+            outputs.set_codes(RadioDisplay::UpperActive, [0, 0x0A, 0, 0, 0]),
+            Err(Error::InvalidDisplayText { display: "upper active", text: "0x0a".to_string() })
+        );
+        assert!(outputs.set_code(RadioDisplay::UpperActive, 5, 0).is_err());
+        assert!(outputs.set_number(RadioDisplay::UpperActive, 100.0, NumberFormat::new(3)).is_err());
+        assert_eq!(outputs.display(RadioDisplay::UpperActive), [0xff, 7, 7, 0, 0]);
+    }
+
+    #[test]
+    fn mock_device() {
+        let inputs = RadioPanelInputs::new().with_selector1(ComSelection::NAV1).with_selector2(ComSelection::XPDR).with_swap1(true);
+        let (mock, cmd_tx, _inputs) = mock_panel(RadioPanel::receive_from, PanelKind::Radio, RadioPanelOutputs::default().as_bytes(), inputs.into(), |id| crate::InputData::RadioInputData(id, inputs));
+        cmd_tx.send(OutputCommands::SetUpperActiveFrequency(118.25)).unwrap();
+        let report = mock.next_feature_report(Duration::from_secs(2)).expect("no report sent");
+        assert_eq!(report[1..6], [1, 1, 0xD8, 2, 5]);
+    }
+
+    #[test]
+    fn errors_are_reported() {
+        let mock = MockTransport::new();
+        let (tx, rx) = mpsc::channel();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        RadioPanel::receive_from(mock, tx, cmd_rx);
+
+        cmd_tx.send(OutputCommands::SetUpperActiveFrequency(-1.0)).unwrap();
+        assert_eq!(
+            rx.recv_timeout(Duration::from_secs(2)),
+            Ok(crate::InputData::DeviceError(crate::DeviceId::new(PanelKind::Radio, ""), Error::ValueOutOfRange { display: "upper active", value: -1.0 }))
+        );
+    }
+
+    #[test]
+    fn initial_state() {
+        let mock = MockTransport::new();
+        let inputs = RadioPanelInputs::new().with_selector1(ComSelection::COM2).with_selector2(ComSelection::DME);
+        mock.set_input_report(&u32::from(inputs).to_le_bytes());
+        let (tx, rx) = mpsc::channel();
+        let (_cmd_tx, cmd_rx) = mpsc::channel();
+        let handle = RadioPanel::receive_from(mock.clone(), tx, cmd_rx);
+
+        let id = crate::DeviceId::new(PanelKind::Radio, "");
+        assert_eq!(rx.recv_timeout(Duration::from_secs(2)), Ok(crate::InputData::RadioInputData(id.clone(), inputs)));
+        assert_eq!(handle.current_state(), Some(crate::InputData::RadioInputData(id.clone(), inputs)));
+
+        let inputs = inputs.with_selector1(ComSelection::NAV2);
+        mock.push_input(&u32::from(inputs).to_le_bytes());
+        assert!(rx.recv_timeout(Duration::from_secs(2)).is_ok());
+        assert_eq!(handle.current_state(), Some(crate::InputData::RadioInputData(id, inputs)));
+    }
+}
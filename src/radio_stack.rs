@@ -0,0 +1,632 @@
+use crate::InputData;
+use crate::dme::{Dme, DmeReading, DmeSource};
+use crate::encoders::Knob;
+use crate::error::{Error, Result};
+use crate::events::{Button, Event, EventDecoder, Selector};
+use crate::radio_panel::{ComSelection, RadioPanelOutputs, RadioRow};
+use crate::segments::{self, Align, BLANK};
+use crate::transponder::{Emergency, Squawk, Transponder, TransponderMode, XpdrSwap};
+
+/*
+Tuning rules, values are kept in kHz
+
+COM1/COM2  118.000-136.975 (136.990 with 8.33 kHz spacing)
+           outer knob: MHz, 136 wraps to 118
+           inner knob: channel within the MHz, .975 (.990) wraps to .000
+           8.33 kHz spacing has 16 channel names per 100 kHz: .000 .005 .010 .015 .025 .030 .035 .040
+           .050 .055 .060 .065 .075 .080 .085 .090
+NAV1/NAV2  108.00-117.95, outer knob MHz, inner knob 50 kHz, both wrapping like COM
+ADF        190-1799 kHz, outer knob 100 kHz, inner knob 1 kHz, wrapping at the ends of the band
+XPDR       0000-7777 octal, edited one digit at a time, see transponder.rs
+DME        nothing to tune, shows what the application sets, see dme.rs
+
+the knobs tune the standby value of COM, NAV and ADF, the swap button exchanges it with the active one
+unless the swap handler vetoes or overrides it, every swap done is reported as FrequencySwapped
+
+displays have 5 digits: COM shows 118.27 for 118.275 with 25 kHz spacing and 18.005 (without
+the leading 1) with 8.33 kHz spacing, NAV 108.50, ADF right aligned without decimals
+*/
+
+const COM_MHZ: (u32, u32) = (118, 136);
+const NAV_MHZ: (u32, u32) = (108, 117);
+const ADF_KHZ: (u32, u32) = (190, 1799);
+const NAV_CHANNELS: Channels = Channels { block: 50, per_block: 1, step: 50 };
+
+/// Channel spacing of the COM radios.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Spacing {
+    Khz25,
+    Khz8_33
+}
+
+/// Active and standby value of a radio, in kHz.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Tuning {
+    pub active: u32,
+    pub standby: u32
+}
+
+impl Tuning {
+    fn new(value: u32) -> Self {
+        Tuning { active: value, standby: value }
+    }
+}
+
+/// What the stack did in response to the panel, collected until `events()` is called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RadioEvent {
+    /// The swap button of `row` exchanged (or the handler replaced) the values of `mode`.
+    FrequencySwapped { row: RadioRow, mode: ComSelection, active: u32, standby: u32 },
+    /// A code dialed on the panel is squawked, `emergency` is set for 7500, 7600 and 7700.
+    SquawkChanged { squawk: Squawk, emergency: Option<Emergency> },
+    TransponderModeChanged(TransponderMode),
+    /// The swap button sent IDENT (see `set_xpdr_swap`).
+    Ident(Squawk)
+}
+
+/// Answer of a swap handler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SwapDecision {
+    /// Exchange active and standby value.
+    Swap,
+    /// Leave both values, e.g. when the simulator will send the swapped values itself.
+    Veto,
+    /// Use these values instead, ignored if they are not valid for the mode.
+    Set { active: u32, standby: u32 }
+}
+
+type SwapHandler = Box<dyn FnMut(RadioRow, ComSelection, Tuning) -> SwapDecision + Send>;
+
+/// A COM, NAV, ADF and transponder stack driven by the radio panel.
+///
+/// Feed it the inputs of one radio panel and send what it returns to that panel:
+///
+/// ```no_run
+/// # use flightpanels_rs::prelude::*;
+/// # use flightpanels_rs::radio_stack::RadioStack;
+/// let panels = Flightpanels::new().unwrap();
+/// let mut radios = RadioStack::new();
+/// for input in panels.inputs() {
+///     if let Some(outputs) = radios.update(&input) {
+///         panels.radio_panel().send(RadioPanelCommands::SetDisplays(outputs)).unwrap();
+///     }
+///     for event in radios.events() {
+///         println!("{:?}", event);
+///     }
+/// }
+/// ```
+pub struct RadioStack {
+    com1: Tuning,
+    com2: Tuning,
+    nav1: Tuning,
+    nav2: Tuning,
+    adf: Tuning,
+    transponder: Transponder,
+    dme: Dme,
+    spacing: Spacing,
+    /// Mode selected on the upper and lower row.
+    selection: [ComSelection; 2],
+    decoder: EventDecoder,
+    /// Displays last returned by `update`.
+    shown: Option<RadioPanelOutputs>,
+    events: Vec<RadioEvent>,
+    swap_handler: Option<SwapHandler>
+}
+
+impl Default for RadioStack {
+    fn default() -> Self {
+        RadioStack {
+            com1: Tuning::new(118_000),
+            com2: Tuning::new(118_000),
+            nav1: Tuning::new(108_000),
+            nav2: Tuning::new(108_000),
+            adf: Tuning::new(ADF_KHZ.0),
+            transponder: Transponder::default(),
+            dme: Dme::default(),
+            spacing: Spacing::Khz25,
+            selection: [ComSelection::Invalid; 2],
+            decoder: EventDecoder::new(),
+            shown: None,
+            events: Vec::new(),
+            swap_handler: None
+        }
+    }
+}
+
+impl RadioStack {
+    /// COM at 118.000, NAV at 108.00, ADF at 190, squawk 7000 in standby and 25 kHz spacing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn spacing(&self) -> Spacing {
+        self.spacing
+    }
+
+    /// Changes the COM channel spacing, moving values to the nearest lower channel of the new spacing.
+    pub fn set_spacing(&mut self, spacing: Spacing) {
+        self.spacing = spacing;
+        for tuning in [&mut self.com1, &mut self.com2] {
+            tuning.active = snap(tuning.active, Channels::com(spacing));
+            tuning.standby = snap(tuning.standby, Channels::com(spacing));
+        }
+    }
+
+    /// Active and standby value of a mode, `None` for DME and XPDR.
+    pub fn tuning(&self, mode: ComSelection) -> Option<Tuning> {
+        match mode {
+            ComSelection::COM1 => Some(self.com1),
+            ComSelection::COM2 => Some(self.com2),
+            ComSelection::NAV1 => Some(self.nav1),
+            ComSelection::NAV2 => Some(self.nav2),
+            ComSelection::ADF => Some(self.adf),
+            _ => None
+        }
+    }
+
+    fn tuning_mut(&mut self, mode: ComSelection) -> Option<&mut Tuning> {
+        match mode {
+            ComSelection::COM1 => Some(&mut self.com1),
+            ComSelection::COM2 => Some(&mut self.com2),
+            ComSelection::NAV1 => Some(&mut self.nav1),
+            ComSelection::NAV2 => Some(&mut self.nav2),
+            ComSelection::ADF => Some(&mut self.adf),
+            _ => None
+        }
+    }
+
+    /// Sets the active value of a mode, e.g. to follow the simulator.
+    pub fn set_active(&mut self, mode: ComSelection, khz: u32) -> Result<()> {
+        self.check(mode, khz)?;
+        if let Some(tuning) = self.tuning_mut(mode) {
+            tuning.active = khz;
+        }
+        Ok(())
+    }
+
+    pub fn set_standby(&mut self, mode: ComSelection, khz: u32) -> Result<()> {
+        self.check(mode, khz)?;
+        if let Some(tuning) = self.tuning_mut(mode) {
+            tuning.standby = khz;
+        }
+        Ok(())
+    }
+
+    fn check(&self, mode: ComSelection, khz: u32) -> Result<()> {
+        let valid = match mode {
+            ComSelection::COM1 | ComSelection::COM2 => in_band(khz, COM_MHZ) && snap(khz, Channels::com(self.spacing)) == khz,
+            ComSelection::NAV1 | ComSelection::NAV2 => in_band(khz, NAV_MHZ) && snap(khz, NAV_CHANNELS) == khz,
+            ComSelection::ADF => (ADF_KHZ.0..=ADF_KHZ.1).contains(&khz),
+            _ => false
+        };
+        if valid { Ok(()) } else { Err(Error::ValueOutOfRange { display: mode_name(mode), value: khz as f64 }) }
+    }
+
+    /// Transponder code squawked, not one still being dialed on the panel.
+    pub fn squawk(&self) -> Squawk {
+        self.transponder.squawk
+    }
+
+    /// Sets the transponder code, e.g. to follow the simulator, dropping a code being dialed.
+    pub fn set_squawk(&mut self, squawk: Squawk) {
+        self.transponder.set_squawk(squawk);
+    }
+
+    pub fn transponder_mode(&self) -> TransponderMode {
+        self.transponder.mode
+    }
+
+    pub fn set_transponder_mode(&mut self, mode: TransponderMode) {
+        self.transponder.mode = mode;
+    }
+
+    /// What the swap button does on a row showing the transponder, `XpdrSwap::NextDigit` by default.
+    pub fn set_xpdr_swap(&mut self, swap: XpdrSwap) {
+        self.transponder.swap = swap;
+    }
+
+    /// Sets what the DME receives, `None` without a signal.
+    pub fn set_dme(&mut self, reading: Option<DmeReading>) {
+        self.dme.reading = reading;
+    }
+
+    pub fn dme_source(&self) -> DmeSource {
+        self.dme.source
+    }
+
+    /// NAV receiver the DME follows, shown in the first digit of the distance.
+    pub fn set_dme_source(&mut self, source: DmeSource) {
+        self.dme.source = source;
+    }
+
+    /// Mode selected on a row, `Invalid` before the first report of the panel.
+    pub fn selection(&self, row: RadioRow) -> ComSelection {
+        self.selection[row_index(row)]
+    }
+
+    /// Exchanges active and standby value of a mode, false for modes without standby value.
+    ///
+    /// Unlike the swap button this neither asks the swap handler nor reports an event.
+    pub fn swap(&mut self, mode: ComSelection) -> bool {
+        match self.tuning_mut(mode) {
+            Some(tuning) => {
+                std::mem::swap(&mut tuning.active, &mut tuning.standby);
+                true
+            },
+            None => false
+        }
+    }
+
+    /// Lets the application decide about every swap button press before it is applied.
+    ///
+    /// ```
+    /// # use flightpanels_rs::radio_stack::{RadioStack, SwapDecision};
+    /// let mut radios = RadioStack::new();
+    /// // the simulator owns the frequencies, it is told about the swap and sends the result back
+    /// radios.set_swap_handler(|_row, _mode, _tuning| SwapDecision::Veto);
+    /// ```
+    pub fn set_swap_handler(&mut self, handler: impl FnMut(RadioRow, ComSelection, Tuning) -> SwapDecision + Send + 'static) {
+        self.swap_handler = Some(Box::new(handler));
+    }
+
+    /// Events since the last call.
+    pub fn events(&mut self) -> Vec<RadioEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    /// The swap button of `row` was pressed.
+    fn press_swap(&mut self, row: RadioRow) -> bool {
+        let mode = self.selection(row);
+        if mode == ComSelection::XPDR {
+            self.transponder.press_swap(&mut self.events);
+            return true;
+        }
+        if mode == ComSelection::DME {
+            self.dme.press_swap();
+            return true;
+        }
+        let Some(tuning) = self.tuning(mode) else { return false };
+        let decision = match self.swap_handler.as_mut() {
+            Some(handler) => handler(row, mode, tuning),
+            None => SwapDecision::Swap
+        };
+        let (active, standby) = match decision {
+            SwapDecision::Swap => (tuning.standby, tuning.active),
+            SwapDecision::Set { active, standby } if self.check(mode, active).is_ok() && self.check(mode, standby).is_ok() => (active, standby),
+            SwapDecision::Set { .. } | SwapDecision::Veto => return false
+        };
+        if let Some(tuning) = self.tuning_mut(mode) {
+            *tuning = Tuning { active, standby };
+        }
+        self.events.push(RadioEvent::FrequencySwapped { row, mode, active, standby });
+        true
+    }
+
+    /// Turns the inner (`coarse` false) or outer knob of the mode by `steps` channels.
+    ///
+    /// For XPDR the outer knob moves between the digits and the mode, the inner knob changes them.
+    pub fn tune(&mut self, mode: ComSelection, coarse: bool, steps: i32) {
+        let spacing = self.spacing;
+        match mode {
+            ComSelection::COM1 | ComSelection::COM2 => if let Some(tuning) = self.tuning_mut(mode) {
+                tuning.standby = step(tuning.standby, COM_MHZ, Channels::com(spacing), coarse, steps);
+            },
+            ComSelection::NAV1 | ComSelection::NAV2 => if let Some(tuning) = self.tuning_mut(mode) {
+                tuning.standby = step(tuning.standby, NAV_MHZ, NAV_CHANNELS, coarse, steps);
+            },
+            ComSelection::ADF => {
+                let (low, high) = ADF_KHZ;
+                let delta = if coarse { steps as i64 * 100 } else { steps as i64 };
+                let offset = (self.adf.standby - low) as i64 + delta;
+                self.adf.standby = low + offset.rem_euclid((high - low + 1) as i64) as u32;
+            },
+            ComSelection::XPDR if coarse => self.transponder.move_cursor(steps, &mut self.events),
+            ComSelection::XPDR => self.transponder.turn(steps, &mut self.events),
+            _ => ()
+        }
+    }
+
+    /// Applies one event of the radio panel, true if any value or selection changed.
+    pub fn handle(&mut self, event: &Event) -> bool {
+        match event {
+            Event::SelectorChanged(Selector::Radio(row, mode)) => {
+                // a code being dialed is squawked once the row shows something else
+                self.transponder.commit(&mut self.events);
+                self.selection[row_index(*row)] = *mode;
+                true
+            },
+            Event::ButtonPressed(Button::Swap(row)) => self.press_swap(*row),
+            Event::KnobTurned { knob: Knob::Fine(row), steps, .. } => {
+                self.tune(self.selection(*row), false, *steps);
+                true
+            },
+            Event::KnobTurned { knob: Knob::Coarse(row), steps, .. } => {
+                self.tune(self.selection(*row), true, *steps);
+                true
+            },
+            _ => false
+        }
+    }
+
+    /// Applies a report of the radio panel, returns the displays to send when they changed.
+    pub fn update(&mut self, input: &InputData) -> Option<RadioPanelOutputs> {
+        if !matches!(input, InputData::RadioInputData(..)) {
+            return None;
+        }
+        for event in self.decoder.decode(input) {
+            self.handle(&event.event);
+        }
+        let outputs = self.outputs();
+        if self.shown == Some(outputs) {
+            return None;
+        }
+        self.shown = Some(outputs);
+        Some(outputs)
+    }
+
+    /// The four displays for the current selection.
+    pub fn outputs(&self) -> RadioPanelOutputs {
+        let (upper_active, upper_standby) = self.displays(self.selection[0]);
+        let (lower_active, lower_standby) = self.displays(self.selection[1]);
+        RadioPanelOutputs {
+            upper_active_display: upper_active,
+            upper_standby_display: upper_standby,
+            lower_active_display: lower_active,
+            lower_standby_display: lower_standby
+        }
+    }
+
+    fn displays(&self, mode: ComSelection) -> ([u8; 5], [u8; 5]) {
+        let text = |khz: u32| match mode {
+            ComSelection::COM1 | ComSelection::COM2 => match self.spacing {
+                Spacing::Khz25 => format!("{}.{:02}", khz / 1000, khz % 1000 / 10),
+                Spacing::Khz8_33 => format!("{}.{:03}", khz / 1000 % 100, khz % 1000)
+            },
+            ComSelection::NAV1 | ComSelection::NAV2 => format!("{}.{:02}", khz / 1000, khz % 1000 / 10),
+            _ => khz.to_string()
+        };
+        match (mode, self.tuning(mode)) {
+            (_, Some(tuning)) => (digits(&text(tuning.active)), digits(&text(tuning.standby))),
+            (ComSelection::XPDR, None) => self.transponder.displays(),
+            (ComSelection::DME, None) => self.dme.displays(),
+            _ => ([BLANK; 5], [BLANK; 5])
+        }
+    }
+}
+
+fn mode_name(mode: ComSelection) -> &'static str {
+    match mode {
+        ComSelection::COM1 => "COM1",
+        ComSelection::COM2 => "COM2",
+        ComSelection::NAV1 => "NAV1",
+        ComSelection::NAV2 => "NAV2",
+        ComSelection::ADF => "ADF",
+        ComSelection::DME => "DME",
+        ComSelection::XPDR => "XPDR",
+        ComSelection::Invalid => "radio"
+    }
+}
+
+fn row_index(row: RadioRow) -> usize {
+    match row {
+        RadioRow::Upper => 0,
+        RadioRow::Lower => 1
+    }
+}
+
+fn in_band(khz: u32, (low, high): (u32, u32)) -> bool {
+    (low..=high).contains(&(khz / 1000))
+}
+
+/// Channels within a MHz: `per_block` channels `step` kHz apart at the start of every `block` kHz.
+#[derive(Debug, Clone, Copy)]
+struct Channels {
+    block: u32,
+    per_block: u32,
+    step: u32
+}
+
+impl Channels {
+    fn com(spacing: Spacing) -> Self {
+        match spacing {
+            Spacing::Khz25 => Channels { block: 25, per_block: 1, step: 25 },
+            // .000 .005 .010 .015 in every 25 kHz
+            Spacing::Khz8_33 => Channels { block: 25, per_block: 4, step: 5 }
+        }
+    }
+
+    fn count(&self) -> u32 {
+        1000 / self.block * self.per_block
+    }
+
+    /// kHz within the MHz of channel `index`.
+    fn khz(&self, index: u32) -> u32 {
+        index / self.per_block * self.block + index % self.per_block * self.step
+    }
+
+    /// Index of the nearest channel at or below `khz` within the MHz.
+    fn index(&self, khz: u32) -> u32 {
+        khz / self.block * self.per_block + (khz % self.block / self.step).min(self.per_block - 1)
+    }
+}
+
+/// Nearest channel at or below `khz`.
+fn snap(khz: u32, channels: Channels) -> u32 {
+    khz - khz % 1000 + channels.khz(channels.index(khz % 1000))
+}
+
+/// Moves `khz` by `steps` MHz or channels, wrapping within the band or the MHz.
+fn step(khz: u32, (low, high): (u32, u32), channels: Channels, coarse: bool, steps: i32) -> u32 {
+    let mhz = khz / 1000;
+    let index = channels.index(khz % 1000);
+    if coarse {
+        let offset = (mhz - low) as i64 + steps as i64;
+        (low + offset.rem_euclid((high - low + 1) as i64) as u32) * 1000 + channels.khz(index)
+    }
+    else {
+        mhz * 1000 + channels.khz((index as i64 + steps as i64).rem_euclid(channels.count() as i64) as u32)
+    }
+}
+
+/// Digit codes of text formatted by the stack, right aligned.
+fn digits(text: &str) -> [u8; 5] {
+    segments::text(text, Align::Right).unwrap_or([BLANK; 5])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{DeviceId, PanelKind};
+    use crate::radio_panel::RadioPanelInputs;
+
+    #[test]
+    fn channels() {
+        let names: Vec<u32> = (0..16).map(|index| Channels::com(Spacing::Khz8_33).khz(index)).collect();
+        assert_eq!(names, [0, 5, 10, 15, 25, 30, 35, 40, 50, 55, 60, 65, 75, 80, 85, 90]);
+        for (channels, last) in [(Channels::com(Spacing::Khz25), 975), (Channels::com(Spacing::Khz8_33), 990), (NAV_CHANNELS, 950)] {
+            assert!((0..channels.count()).all(|index| channels.index(channels.khz(index)) == index));
+            assert_eq!(channels.khz(channels.count() - 1), last);
+        }
+        assert_eq!(snap(118_024, Channels::com(Spacing::Khz8_33)), 118_015);
+        assert_eq!(snap(118_024, Channels::com(Spacing::Khz25)), 118_000);
+        assert_eq!(snap(108_099, NAV_CHANNELS), 108_050);
+    }
+
+    #[test]
+    fn com_tuning() {
+        let mut radios = RadioStack::new();
+        radios.set_standby(ComSelection::COM1, 118_975).unwrap();
+        radios.tune(ComSelection::COM1, false, 1);
+        assert_eq!(radios.tuning(ComSelection::COM1).unwrap().standby, 118_000);
+        radios.tune(ComSelection::COM1, false, -2);
+        assert_eq!(radios.tuning(ComSelection::COM1).unwrap().standby, 118_950);
+        radios.tune(ComSelection::COM1, true, -1);
+        assert_eq!(radios.tuning(ComSelection::COM1).unwrap().standby, 136_950);
+        radios.tune(ComSelection::COM1, true, 1);
+        assert_eq!(radios.tuning(ComSelection::COM1).unwrap().standby, 118_950);
+        assert!(radios.set_standby(ComSelection::COM1, 137_000).is_err());
+        assert!(radios.set_standby(ComSelection::COM1, 118_005).is_err());
+
+        radios.set_spacing(Spacing::Khz8_33);
+        radios.set_standby(ComSelection::COM2, 121_490).unwrap();
+        for expected in [121_500, 121_505, 121_510, 121_515, 121_525] {
+            radios.tune(ComSelection::COM2, false, 1);
+            assert_eq!(radios.tuning(ComSelection::COM2).unwrap().standby, expected);
+        }
+        radios.set_standby(ComSelection::COM2, 121_990).unwrap();
+        radios.tune(ComSelection::COM2, false, 1);
+        assert_eq!(radios.tuning(ComSelection::COM2).unwrap().standby, 121_000);
+        radios.set_standby(ComSelection::COM2, 121_015).unwrap();
+        radios.set_spacing(Spacing::Khz25);
+        assert_eq!(radios.tuning(ComSelection::COM2).unwrap().standby, 121_000);
+    }
+
+    #[test]
+    fn nav_adf_xpdr() {
+        let mut radios = RadioStack::new();
+        radios.tune(ComSelection::NAV2, false, -1);
+        assert_eq!(radios.tuning(ComSelection::NAV2).unwrap().standby, 108_950);
+        radios.tune(ComSelection::NAV2, true, 9);
+        assert_eq!(radios.tuning(ComSelection::NAV2).unwrap().standby, 117_950);
+        radios.tune(ComSelection::NAV2, true, 1);
+        assert_eq!(radios.tuning(ComSelection::NAV2).unwrap().standby, 108_950);
+
+        radios.tune(ComSelection::ADF, false, -1);
+        assert_eq!(radios.tuning(ComSelection::ADF).unwrap().standby, 1799);
+        radios.tune(ComSelection::ADF, true, 1);
+        assert_eq!(radios.tuning(ComSelection::ADF).unwrap().standby, 289);
+
+        radios.tune(ComSelection::XPDR, false, -1);
+        assert_eq!(radios.squawk(), Squawk::default());
+        radios.tune(ComSelection::XPDR, true, 3);
+        assert_eq!(radios.squawk(), Squawk::new(6000).unwrap());
+        radios.tune(ComSelection::XPDR, false, 1);
+        radios.tune(ComSelection::XPDR, true, 1);
+        assert_eq!(radios.squawk(), Squawk::new(6001).unwrap());
+        radios.tune(ComSelection::XPDR, false, -2);
+        assert_eq!(radios.transponder_mode(), TransponderMode::Off);
+        assert_eq!(radios.events(), vec![
+            RadioEvent::SquawkChanged { squawk: Squawk::new(6000).unwrap(), emergency: None },
+            RadioEvent::SquawkChanged { squawk: Squawk::new(6001).unwrap(), emergency: None },
+            RadioEvent::TransponderModeChanged(TransponderMode::Off)
+        ]);
+    }
+
+    #[test]
+    fn drives_displays() {
+        let radio = DeviceId::new(PanelKind::Radio, "");
+        let mut radios = RadioStack::new();
+        radios.set_active(ComSelection::COM1, 121_500).unwrap();
+        let inputs = RadioPanelInputs::new().with_selector1(ComSelection::COM1).with_selector2(ComSelection::XPDR);
+        let outputs = radios.update(&InputData::RadioInputData(radio.clone(), inputs)).unwrap();
+        assert_eq!(outputs.upper_active_display, [1, 2, 0xD1, 5, 0]);
+        assert_eq!(outputs.upper_standby_display, [1, 1, 0xD8, 0, 0]);
+        assert_eq!(outputs.lower_active_display, [BLANK, 0xD7, 0, 0, 0]);
+        assert_eq!(outputs.lower_standby_display, [BLANK, BLANK, BLANK, BLANK, 1]);
+        assert_eq!(radios.update(&InputData::RadioInputData(radio.clone(), inputs)), None);
+
+        let outputs = radios.update(&InputData::RadioInputData(radio.clone(), inputs.with_fine_inc1(true).with_coarse_dec1(true))).unwrap();
+        assert_eq!(outputs.upper_standby_display, [1, 3, 0xD6, 0, 2]);
+        let outputs = radios.update(&InputData::RadioInputData(radio.clone(), inputs.with_swap1(true))).unwrap();
+        assert_eq!(outputs.upper_active_display, [1, 3, 0xD6, 0, 2]);
+        assert_eq!(outputs.upper_standby_display, [1, 2, 0xD1, 5, 0]);
+
+        radios.set_spacing(Spacing::Khz8_33);
+        radios.set_active(ComSelection::COM1, 118_005).unwrap();
+        let outputs = radios.update(&InputData::RadioInputData(radio, inputs.with_selector2(ComSelection::ADF))).unwrap();
+        assert_eq!(outputs.upper_active_display, [1, 0xD8, 0, 0, 5]);
+        assert_eq!(outputs.lower_active_display, [BLANK, BLANK, 1, 9, 0]);
+    }
+
+    #[test]
+    fn swap_handler() {
+        let radio = DeviceId::new(PanelKind::Radio, "");
+        let inputs = RadioPanelInputs::new().with_selector1(ComSelection::NAV1).with_selector2(ComSelection::NAV1);
+        let mut radios = RadioStack::new();
+        radios.set_standby(ComSelection::NAV1, 110_500).unwrap();
+        radios.update(&InputData::RadioInputData(radio.clone(), inputs));
+        radios.update(&InputData::RadioInputData(radio.clone(), inputs.with_swap2(true)));
+        assert_eq!(radios.events(), vec![RadioEvent::FrequencySwapped { row: RadioRow::Lower, mode: ComSelection::NAV1, active: 110_500, standby: 108_000 }]);
+        assert_eq!(radios.events(), vec![]);
+
+        let mut decisions = vec![SwapDecision::Set { active: 113_000, standby: 109_000 }, SwapDecision::Set { active: 1, standby: 2 }, SwapDecision::Veto];
+        radios.set_swap_handler(move |row, mode, tuning| {
+            assert_eq!((row, mode), (RadioRow::Upper, ComSelection::NAV1));
+            assert_eq!(tuning.standby % 1000, 0);
+            decisions.pop().unwrap()
+        });
+        for _ in 0..3 {
+            radios.update(&InputData::RadioInputData(radio.clone(), inputs));
+            radios.update(&InputData::RadioInputData(radio.clone(), inputs.with_swap1(true)));
+        }
+        assert_eq!(radios.events(), vec![RadioEvent::FrequencySwapped { row: RadioRow::Upper, mode: ComSelection::NAV1, active: 113_000, standby: 109_000 }]);
+        assert_eq!(radios.outputs().upper_active_display, [1, 1, 0xD3, 0, 0]);
+    }
+
+    #[test]
+    fn transponder_and_dme_rows() {
+        let radio = DeviceId::new(PanelKind::Radio, "");
+        let inputs = RadioPanelInputs::new().with_selector1(ComSelection::XPDR).with_selector2(ComSelection::COM1);
+        let mut radios = RadioStack::new();
+        radios.set_xpdr_swap(XpdrSwap::Ident);
+        radios.update(&InputData::RadioInputData(radio.clone(), inputs));
+        let outputs = radios.update(&InputData::RadioInputData(radio.clone(), inputs.with_fine_inc1(true))).unwrap();
+        assert_eq!(outputs.upper_active_display, [BLANK, 0xD0, 0, 0, 0]);
+        assert_eq!(radios.squawk(), Squawk::default());
+        radios.update(&InputData::RadioInputData(radio.clone(), inputs.with_swap1(true)));
+        assert_eq!(radios.events(), vec![RadioEvent::SquawkChanged { squawk: Squawk::new(0).unwrap(), emergency: None }, RadioEvent::Ident(Squawk::new(0).unwrap())]);
+
+        // the code dialed is squawked when the row is switched away from XPDR
+        radios.update(&InputData::RadioInputData(radio.clone(), inputs.with_fine_dec1(true)));
+        assert_eq!(radios.squawk(), Squawk::new(0).unwrap());
+        radios.update(&InputData::RadioInputData(radio.clone(), inputs.with_selector1(ComSelection::DME)));
+        assert_eq!(radios.events(), vec![RadioEvent::SquawkChanged { squawk: Squawk::default(), emergency: None }]);
+
+        // DME without signal shows dashes, the swap button switches to time to station
+        assert_eq!(radios.outputs().upper_active_display, [1, BLANK, 0xEE, 0xEE, 0xEE]);
+        radios.set_dme(Some(DmeReading { distance: Some(5.0), speed: Some(120.0), time_to_station: Some(2.5) }));
+        radios.update(&InputData::RadioInputData(radio, inputs.with_selector1(ComSelection::DME).with_swap1(true)));
+        assert_eq!(radios.outputs().upper_active_display, [1, BLANK, BLANK, 0xD5, 0]);
+        assert_eq!(radios.outputs().upper_standby_display, [BLANK, BLANK, BLANK, BLANK, 3]);
+    }
+}
@@ -0,0 +1,415 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+use crate::{DeviceId, InputData, PanelKind};
+use crate::device::{self, Control, PanelHandle};
+use crate::error::{Error, Result};
+use crate::transport::{MockTransport, Transport};
+use crate::{flight_instrument_panel, multi_panel, radio_panel, switch_panel};
+
+/*
+Recordings are text files with one report per line, lines starting with # are comments:
+
+<microseconds since recording started> <panel> <serial> <direction> <data as hex>
+
+panel: multi, radio, switch or fip
+serial: serial number of the panel (its path if it has none), - if unknown, spaces and % written as %20 and %25
+direction:
+  in       input report read from the panel
+  report   input report requested from the panel when it connected (without report id)
+  feature  feature report sent to the panel (radio, multi and switch panel displays and LEDs)
+  write    data sent to the panel (FIP DirectOutput requests)
+
+example:
+# flightpanels recording
+0 switch 0000A1B2 report 000000
+1520 switch 0000A1B2 feature 0000
+873311 switch 0000A1B2 in 010000
+*/
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Direction {
+    In,
+    Report,
+    Feature,
+    Write
+}
+
+impl Direction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Direction::In => "in",
+            Direction::Report => "report",
+            Direction::Feature => "feature",
+            Direction::Write => "write"
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Direction> {
+        [Direction::In, Direction::Report, Direction::Feature, Direction::Write].into_iter().find(|direction| direction.name() == name)
+    }
+}
+
+/// Writes every report of the panels to a recording, see [`Flightpanels::record`](crate::Flightpanels::record).
+pub struct Recorder {
+    out: Mutex<Box<dyn Write + Send>>,
+    start: Instant
+}
+
+impl Recorder {
+    pub fn new(out: impl Write + Send + 'static) -> Self {
+        let mut out: Box<dyn Write + Send> = Box::new(out);
+        let _ = writeln!(out, "# flightpanels recording");
+        Recorder { out: Mutex::new(out), start: Instant::now() }
+    }
+
+    pub fn create(path: impl AsRef<Path>) -> Result<Self> {
+        Ok(Recorder::new(BufWriter::new(File::create(path)?)))
+    }
+
+    /// Appends one line, write errors are ignored so a full disk does not stop the panels.
+    pub fn record(&self, device: &DeviceId, direction: Direction, data: &[u8]) {
+        let micros = self.start.elapsed().as_micros();
+        let mut out = self.out.lock().expect("recorder poisoned");
+        let _ = writeln!(out, "{} {} {} {} {}", micros, device.kind.name(), escape(&device.serial), direction.name(), to_hex(data));
+        let _ = out.flush();
+    }
+}
+
+/// One line of a recording.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub time: Duration,
+    pub device: DeviceId,
+    pub direction: Direction,
+    pub data: Vec<u8>
+}
+
+/// A parsed recording.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Recording {
+    pub entries: Vec<Entry>
+}
+
+impl Recording {
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        let lines = BufReader::new(File::open(path)?).lines().collect::<std::io::Result<Vec<String>>>()?;
+        Recording::parse(&lines.join("\n"))
+    }
+
+    pub fn parse(text: &str) -> Result<Self> {
+        let mut entries = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |message: &str| Error::InvalidRecording { line: number + 1, message: message.to_string() };
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let [micros, kind, serial, direction, data] = fields[..] else {
+                return Err(invalid("expected 5 fields"));
+            };
+            entries.push(Entry {
+                time: Duration::from_micros(micros.parse().map_err(|_| invalid("invalid time"))?),
+                device: DeviceId::new(PanelKind::from_name(kind).ok_or_else(|| invalid("unknown panel"))?, unescape(serial)),
+                direction: Direction::from_name(direction).ok_or_else(|| invalid("unknown direction"))?,
+                data: from_hex(data).ok_or_else(|| invalid("invalid hex data"))?
+            });
+        }
+        Ok(Recording { entries })
+    }
+
+    /// All input reports decoded the same way the panel threads do, with the time they were read.
+    pub fn inputs(&self) -> Vec<(Duration, InputData)> {
+        self.entries.iter()
+            .filter(|entry| matches!(entry.direction, Direction::In | Direction::Report))
+            .map(|entry| (entry.time, device::decode(&entry.device, report(&entry.data))))
+            .collect()
+    }
+
+    /// Every panel of the recording.
+    pub fn devices(&self) -> Vec<DeviceId> {
+        let mut devices: Vec<DeviceId> = Vec::new();
+        for entry in &self.entries {
+            if !devices.contains(&entry.device) {
+                devices.push(entry.device.clone());
+            }
+        }
+        devices
+    }
+
+    /// Plays the recording through panel threads on mock transports.
+    ///
+    /// `speed` scales the recorded timing, 1.0 is real time and `f64::INFINITY` as fast as
+    /// possible, where reports of different panels may then arrive in a different order.
+    pub fn replay(&self, speed: f64) -> Replay {
+        let (tx, rx) = mpsc::channel();
+        let mut devices = Vec::new();
+        let mut handles = Vec::new();
+        for id in self.devices() {
+            let mock = MockTransport::new();
+            // the report asked for on connect is known before the panel thread starts
+            if let Some(entry) = self.entries.iter().find(|entry| entry.device == id && entry.direction == Direction::Report) {
+                mock.set_input_report(&entry.data);
+            }
+            handles.push(spawn(id.clone(), mock.clone(), tx.clone()));
+            devices.push((id, mock));
+        }
+        let inputs: Vec<(Duration, MockTransport, Vec<u8>)> = self.entries.iter()
+            .filter(|entry| entry.direction == Direction::In)
+            .filter_map(|entry| devices.iter().find(|(id, _)| *id == entry.device).map(|(_, mock)| (entry.time, mock.clone(), entry.data.clone())))
+            .collect();
+        let mocks: Vec<MockTransport> = devices.iter().map(|(_, mock)| mock.clone()).collect();
+        let feeder = thread::spawn(move || {
+            let start = Instant::now();
+            for (time, mock, data) in inputs {
+                if speed.is_finite() && speed > 0.0 {
+                    let due = time.div_f64(speed);
+                    if let Some(wait) = due.checked_sub(start.elapsed()) {
+                        thread::sleep(wait);
+                    }
+                }
+                mock.push_input(&data);
+            }
+            // stop the panels once they read everything, which ends the input channel
+            for mock in mocks {
+                mock.wait_until_read(Duration::from_secs(5));
+            }
+            for handle in handles {
+                handle.stop(false);
+            }
+        });
+        Replay { rx, devices, feeder: Some(feeder) }
+    }
+}
+
+fn spawn(id: DeviceId, mock: MockTransport, tx: mpsc::Sender<InputData>) -> PanelHandle {
+    match id.kind {
+        PanelKind::Multi => device::spawn::<multi_panel::MultiPanel, _>(id, mock, tx, mpsc::channel().1),
+        PanelKind::Radio => device::spawn::<radio_panel::RadioPanel, _>(id, mock, tx, mpsc::channel().1),
+        PanelKind::Switch => device::spawn::<switch_panel::SwitchPanel, _>(id, mock, tx, mpsc::channel().1),
+        PanelKind::FlightInstrument => device::spawn::<flight_instrument_panel::FlightInstrumentPanel, _>(id, mock, tx, mpsc::channel().1)
+    }
+}
+
+/// A recording being played, see [`Recording::replay`].
+pub struct Replay {
+    rx: Receiver<InputData>,
+    devices: Vec<(DeviceId, MockTransport)>,
+    feeder: Option<JoinHandle<()>>
+}
+
+impl Replay {
+    /// Inputs as `Flightpanels::inputs()` delivers them, the channel closes at the end of the recording.
+    pub fn inputs(&self) -> &Receiver<InputData> {
+        &self.rx
+    }
+
+    /// The mock standing in for a panel, e.g. to check what was sent to it.
+    pub fn device(&self, id: &DeviceId) -> Option<&MockTransport> {
+        self.devices.iter().find(|(device, _)| device == id).map(|(_, mock)| mock)
+    }
+}
+
+impl Drop for Replay {
+    fn drop(&mut self) {
+        if let Some(feeder) = self.feeder.take() {
+            let _ = feeder.join();
+        }
+    }
+}
+
+/// Transport writing everything passing through it to the recorder of its panel threads, if any.
+pub(crate) struct Recorded<T> {
+    inner: T,
+    id: DeviceId,
+    control: Arc<Control>
+}
+
+impl<T: Transport> Recorded<T> {
+    pub(crate) fn new(inner: T, id: DeviceId, control: Arc<Control>) -> Self {
+        Recorded { inner, id, control }
+    }
+
+    fn record(&self, direction: Direction, data: &[u8]) {
+        if let Some(recorder) = self.control.recorder() {
+            recorder.record(&self.id, direction, data);
+        }
+    }
+}
+
+impl<T: Transport> Transport for Recorded<T> {
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> Result<usize> {
+        let size = self.inner.read_timeout(buf, timeout)?;
+        if size > 0 {
+            self.record(Direction::In, &buf[..size]);
+        }
+        Ok(size)
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        self.record(Direction::Feature, data);
+        self.inner.send_feature_report(data)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        self.record(Direction::Write, data);
+        self.inner.write(data)
+    }
+
+    fn get_input_report(&self, buf: &mut [u8]) -> Result<usize> {
+        let size = self.inner.get_input_report(buf)?;
+        if size > 1 {
+            self.record(Direction::Report, &buf[1..size]);
+        }
+        Ok(size)
+    }
+}
+
+/// The first four bytes of a report, as the panel threads read them.
+fn report(data: &[u8]) -> [u8; 4] {
+    let mut report = [0u8; 4];
+    let size = data.len().min(4);
+    report[..size].copy_from_slice(&data[..size]);
+    report
+}
+
+pub(crate) fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+pub(crate) fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len()).step_by(2).map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok()).collect()
+}
+
+/// Serial as one field of a line: '%', whitespace and control characters are percent-encoded,
+/// an empty serial is "-".
+pub(crate) fn escape(serial: &str) -> String {
+    match serial {
+        "" => return "-".to_string(),
+        "-" => return "%2D".to_string(),
+        _ => ()
+    }
+    let mut escaped = String::with_capacity(serial.len());
+    for c in serial.chars() {
+        if c == '%' || c.is_whitespace() || c.is_control() {
+            let mut utf8 = [0; 4];
+            for byte in c.encode_utf8(&mut utf8).bytes() {
+                escaped.push_str(&format!("%{:02X}", byte));
+            }
+        }
+        else {
+            escaped.push(c);
+        }
+    }
+    escaped
+}
+
+pub(crate) fn unescape(serial: &str) -> String {
+    if serial == "-" {
+        return String::new();
+    }
+    let mut bytes = Vec::with_capacity(serial.len());
+    let mut rest = serial.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        let escaped = tail.get(..2).and_then(|hex| from_hex(std::str::from_utf8(hex).ok()?));
+        match escaped {
+            Some(decoded) if byte == b'%' => {
+                bytes.extend(decoded);
+                rest = &tail[2..];
+            },
+            _ => {
+                bytes.push(byte);
+                rest = tail;
+            }
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::switch_panel::SwitchPanelInputs;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(data)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let buffer = Buffer::default();
+        let recorder = Recorder::new(buffer.clone());
+        let id = DeviceId::new(PanelKind::Switch, "serial with space");
+        recorder.record(&id, Direction::Feature, &[0, 7]);
+        recorder.record(&id, Direction::In, &u32::from(SwitchPanelInputs::new().with_battery(true)).to_le_bytes()[..3]);
+
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let recording = Recording::parse(&text).unwrap();
+        assert_eq!(recording.entries.len(), 2);
+        assert_eq!(recording.entries[0].device, id);
+        assert_eq!(recording.entries[0].direction, Direction::Feature);
+        assert_eq!(recording.entries[0].data, vec![0, 7]);
+        let inputs = recording.inputs();
+        assert_eq!(inputs.len(), 1);
+        assert_eq!(inputs[0].1, InputData::SwitchInputData(id, SwitchPanelInputs::new().with_battery(true)));
+    }
+
+    #[test]
+    fn serials_round_trip() {
+        let serials = ["", "-", "A1B2", "a-b", "two  spaces", "tab\there", "new\nline\r", "100%", "%20", "no\u{a0}break", "bell\u{7}", "\u{2028}", "ünïcode"];
+        let buffer = Buffer::default();
+        let recorder = Recorder::new(buffer.clone());
+        for serial in serials {
+            let escaped = escape(serial);
+            assert!(!escaped.is_empty() && !escaped.chars().any(|c| c.is_whitespace() || c.is_control()), "{:?}", escaped);
+            assert_eq!(unescape(&escaped), serial);
+            recorder.record(&DeviceId::new(PanelKind::Radio, serial), Direction::Feature, &[1]);
+        }
+        let text = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let recorded: Vec<String> = Recording::parse(&text).unwrap().entries.into_iter().map(|entry| entry.device.serial).collect();
+        assert_eq!(recorded, serials);
+        assert_eq!(unescape("50%"), "50%");
+        assert_eq!(unescape("%zz"), "%zz");
+    }
+
+    #[test]
+    fn parse_errors() {
+        assert_eq!(Recording::parse("# comment\n\n0 radio - in 00").map(|recording| recording.entries.len()), Ok(1));
+        assert_eq!(Recording::parse("0 radio - in"), Err(Error::InvalidRecording { line: 1, message: "expected 5 fields".to_string() }));
+        assert_eq!(Recording::parse("0 radio - in 0\n"), Err(Error::InvalidRecording { line: 1, message: "invalid hex data".to_string() }));
+        assert_eq!(Recording::parse("\n0 tv - in 00"), Err(Error::InvalidRecording { line: 2, message: "unknown panel".to_string() }));
+    }
+
+    #[test]
+    fn replay() {
+        let recording = Recording::parse("\
+            0 switch 1 report 010000\n\
+            100 switch 1 feature 0000\n\
+            2000 switch 1 in 030000\n\
+            3000 radio 2 in 040000\n").unwrap();
+        let replay = recording.replay(f64::INFINITY);
+        let inputs: Vec<InputData> = replay.inputs().iter().collect();
+        let switch = DeviceId::new(PanelKind::Switch, "1");
+        assert!(inputs.contains(&InputData::SwitchInputData(switch.clone(), SwitchPanelInputs::new().with_battery(true))));
+        assert!(inputs.contains(&InputData::SwitchInputData(switch.clone(), SwitchPanelInputs::new().with_battery(true).with_alt(true))));
+        assert_eq!(inputs.iter().filter(|input| input.device().kind == PanelKind::Radio).count(), 1);
+        assert!(replay.device(&switch).unwrap().next_feature_report(Duration::ZERO).is_some());
+    }
+}
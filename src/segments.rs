@@ -0,0 +1,179 @@
+/*
+Digit codes of the 5 digit windows of the radio panel
+
+0x00-0x09  digit
+0xD0-0xD9  digit with the decimal point lit
+0xEE       dash, the multi panel's code for it, not checked on radio panel hardware yet
+0xFF       blank
+
+text is rendered from the characters 0-9, ' ' (blank) and '-' (dash), a '.' lights the decimal
+point of the digit before it, so it needs a digit without point in front, e.g. "118.25" is
+[1, 1, 0xD8, 2, 5] and " 7700" is [0xFF, 7, 7, 0, 0]
+
+numbers are rounded half away from zero to a fixed number of decimals, the value scaled by the
+decimals is first cut to 9 decimals, so 118.275 (stored as 118.27499...) rounds to 118.28
+*/
+
+pub const BLANK: u8 = 0xff;
+pub const DASH: u8 = 0xEE;
+/// Added to a digit to light its decimal point.
+pub const POINT: u8 = 0xD0;
+/// Digits of a window.
+pub const WIDTH: usize = 5;
+
+/// Which side of the window shorter text is placed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Align {
+    Left,
+    #[default]
+    Right
+}
+
+/// How `number` renders a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct NumberFormat {
+    /// Digits after the decimal point, the value is rounded to them.
+    pub decimals: usize,
+    /// Fills the window with zeros in front, e.g. 00042, making `align` irrelevant.
+    pub leading_zeros: bool,
+    pub align: Align
+}
+
+impl NumberFormat {
+    /// Right aligned with `decimals` decimals and no leading zeros.
+    pub fn new(decimals: usize) -> Self {
+        NumberFormat { decimals, ..Self::default() }
+    }
+}
+
+/// True for the codes the radio panel shows.
+pub fn is_valid_code(code: u8) -> bool {
+    matches!(code, 0..=9 | 0xD0..=0xD9 | DASH | BLANK)
+}
+
+/// Codes for `text`, `None` if it has other characters, a misplaced '.' or more than 5 digits.
+pub fn text(text: &str, align: Align) -> Option<[u8; WIDTH]> {
+    let mut codes: Vec<u8> = Vec::with_capacity(WIDTH);
+    for c in text.chars() {
+        match (c, codes.last_mut()) {
+            ('.', Some(last)) if *last <= 9 => *last += POINT,
+            ('.', _) => return None,
+            (' ', _) => codes.push(BLANK),
+            ('-', _) => codes.push(DASH),
+            (c, _) => codes.push(c.to_digit(10)? as u8)
+        }
+    }
+    if codes.len() > WIDTH {
+        return None;
+    }
+    let mut window = [BLANK; WIDTH];
+    let start = match align {
+        Align::Left => 0,
+        Align::Right => WIDTH - codes.len()
+    };
+    window[start..start + codes.len()].copy_from_slice(&codes);
+    Some(window)
+}
+
+/// Codes for `value`, `None` if it is not finite or does not fit once rounded (negative values take a dash).
+pub fn number(value: f64, format: NumberFormat) -> Option<[u8; WIDTH]> {
+    if !value.is_finite() || format.decimals >= WIDTH {
+        return None;
+    }
+    let scaled = value.abs() * 10f64.powi(format.decimals as i32);
+    let units = format!("{:.9}", scaled).parse::<f64>().ok()?.round();
+    if units >= 1e5 {
+        return None;
+    }
+    // at least one digit before the point
+    let mut figures = format!("{:0width$}", units as u64, width = format.decimals + 1);
+    let sign = if value < 0.0 && units > 0.0 { "-" } else { "" };
+    if format.leading_zeros {
+        figures = format!("{:0>width$}", figures, width = WIDTH - sign.len());
+    }
+    let point = figures.len() - format.decimals;
+    let rendered = if format.decimals == 0 {
+        format!("{}{}", sign, figures)
+    }
+    else {
+        format!("{}{}.{}", sign, &figures[..point], &figures[point..])
+    };
+    self::text(&rendered, format.align)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn codes() {
+        let valid: Vec<u8> = (0..=255).filter(|code| is_valid_code(*code)).collect();
+        assert_eq!(valid, [&[0, 1, 2, 3, 4, 5, 6, 7, 8, 9][..], &[0xD0, 0xD1, 0xD2, 0xD3, 0xD4, 0xD5, 0xD6, 0xD7, 0xD8, 0xD9], &[DASH, BLANK]].concat());
+    }
+
+    #[test]
+    fn texts() {
+        let right = |t| text(t, Align::Right);
+        assert_eq!(right(""), Some([BLANK; 5]));
+        assert_eq!(right(" 7700"), Some([BLANK, 7, 7, 0, 0]));
+        assert_eq!(right("7700"), Some([BLANK, 7, 7, 0, 0]));
+        assert_eq!(right("118.25"), Some([1, 1, 0xD8, 2, 5]));
+        assert_eq!(right("-----"), Some([DASH; 5]));
+        assert_eq!(right("1.2.3.4.5."), Some([0xD1, 0xD2, 0xD3, 0xD4, 0xD5]));
+        assert_eq!(right("0.5"), Some([BLANK, BLANK, BLANK, 0xD0, 5]));
+        assert_eq!(right("12 -4"), Some([1, 2, BLANK, DASH, 4]));
+        assert_eq!(right("123456"), None);
+        assert_eq!(right(".5"), None);
+        assert_eq!(right("1..5"), None);
+        assert_eq!(right("- .5"), None);
+        assert_eq!(right("-.5"), None);
+        assert_eq!(right("1a"), None);
+        assert_eq!(right("1,5"), None);
+
+        let left = |t| text(t, Align::Left);
+        assert_eq!(left(""), Some([BLANK; 5]));
+        assert_eq!(left("7700"), Some([7, 7, 0, 0, BLANK]));
+        assert_eq!(left("1.5"), Some([0xD1, 5, BLANK, BLANK, BLANK]));
+        assert_eq!(left("---"), Some([DASH, DASH, DASH, BLANK, BLANK]));
+        assert_eq!(left("12345"), Some([1, 2, 3, 4, 5]));
+        assert_eq!(left("123456"), None);
+    }
+
+    #[test]
+    fn numbers() {
+        let fixed = |value, decimals| number(value, NumberFormat::new(decimals));
+        assert_eq!(fixed(0.0, 0), Some([BLANK, BLANK, BLANK, BLANK, 0]));
+        assert_eq!(fixed(0.0, 2), Some([BLANK, BLANK, 0xD0, 0, 0]));
+        assert_eq!(fixed(0.5, 0), Some([BLANK, BLANK, BLANK, BLANK, 1]));
+        assert_eq!(fixed(0.49, 0), Some([BLANK, BLANK, BLANK, BLANK, 0]));
+        assert_eq!(fixed(0.5, 1), Some([BLANK, BLANK, BLANK, 0xD0, 5]));
+        assert_eq!(fixed(0.05, 2), Some([BLANK, BLANK, 0xD0, 0, 5]));
+        assert_eq!(fixed(0.005, 2), Some([BLANK, BLANK, 0xD0, 0, 1]));
+        assert_eq!(fixed(2.5, 0), Some([BLANK, BLANK, BLANK, BLANK, 3]));
+        assert_eq!(fixed(118.275, 2), Some([1, 1, 0xD8, 2, 8]));
+        assert_eq!(fixed(118.1, 2), Some([1, 1, 0xD8, 1, 0]));
+        assert_eq!(fixed(118.25, 3), None);
+        assert_eq!(fixed(18.005, 3), Some([1, 0xD8, 0, 0, 5]));
+        assert_eq!(fixed(999.96, 1), Some([1, 0, 0, 0xD0, 0]));
+        assert_eq!(fixed(9999.96, 1), None);
+        assert_eq!(fixed(999.94, 1), Some([BLANK, 9, 9, 0xD9, 9]));
+        assert_eq!(fixed(99999.4, 0), Some([9, 9, 9, 9, 9]));
+        assert_eq!(fixed(99999.5, 0), None);
+        assert_eq!(fixed(-1.5, 0), Some([BLANK, BLANK, BLANK, DASH, 2]));
+        assert_eq!(fixed(-12.25, 1), Some([BLANK, DASH, 1, 0xD2, 3]));
+        assert_eq!(fixed(-9999.0, 0), Some([DASH, 9, 9, 9, 9]));
+        assert_eq!(fixed(-10000.0, 0), None);
+        assert_eq!(fixed(-0.04, 1), Some([BLANK, BLANK, BLANK, 0xD0, 0]));
+        assert_eq!(fixed(1.0, 5), None);
+        assert_eq!(fixed(f64::NAN, 0), None);
+        assert_eq!(fixed(f64::INFINITY, 0), None);
+
+        let zeros = NumberFormat { decimals: 1, leading_zeros: true, align: Align::Left };
+        assert_eq!(number(4.2, zeros), Some([0, 0, 0, 0xD4, 2]));
+        assert_eq!(number(-4.2, zeros), Some([DASH, 0, 0, 0xD4, 2]));
+        assert_eq!(number(1234.5, zeros), Some([1, 2, 3, 0xD4, 5]));
+        assert_eq!(number(42.0, NumberFormat { leading_zeros: true, ..NumberFormat::default() }), Some([0, 0, 0, 4, 2]));
+        assert_eq!(number(42.0, NumberFormat { align: Align::Left, ..NumberFormat::default() }), Some([4, 2, BLANK, BLANK, BLANK]));
+        assert_eq!(number(0.5, NumberFormat { decimals: 2, align: Align::Left, ..NumberFormat::default() }), Some([0xD0, 5, 0, BLANK, BLANK]));
+    }
+}
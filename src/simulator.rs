@@ -0,0 +1,475 @@
+use std::io::{BufRead, BufReader, Write};
+use std::net::{Ipv4Addr, Ipv6Addr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+use crate::PanelKind;
+use crate::error::{Error, Result};
+use crate::multi_panel::MultiPanelOutputLeds;
+use crate::recording;
+use crate::transport::Transport;
+
+/// Environment variable with the address of a running simulator, e.g. `127.0.0.1:7979`.
+///
+/// When it is set `Flightpanels` drives the simulated panels instead of USB panels.
+pub const ENV: &str = "FLIGHTPANELS_SIMULATOR";
+/// Address the simulator listens on by default.
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:7979";
+/// How long `get_input_report` waits for the simulator to answer.
+const REPORT_TIMEOUT: Duration = Duration::from_millis(200);
+
+/*
+Simulator protocol: text lines over TCP, data as hex like in recordings
+
+a connection starts with one request from the library:
+  list                  simulator answers one line "<panel> <serial>" per panel, then "end"
+  open <panel> <serial> simulator answers "ok" or "error <message>", then the connection is the panel
+
+on an open connection
+  library -> simulator
+    feature <hex>       feature report (radio, multi and switch panel displays and LEDs)
+    write <hex>         DirectOutput request (FIP)
+    report              asks for the current input report
+  simulator -> library
+    in <hex>            input report, sent whenever an input changes
+    report <hex>        answer to report
+
+closing the connection unplugs the panel, panel names and serials as in recordings (multi, radio,
+switch, fip; serials with '%', whitespace and control characters percent-encoded, "-" if empty)
+*/
+
+/// Panels offered by the simulator at `address`.
+pub(crate) fn list(address: &str) -> Result<Vec<(PanelKind, String)>> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_read_timeout(Some(REPORT_TIMEOUT))?;
+    (&stream).write_all(b"list\n")?;
+    let mut panels = Vec::new();
+    for line in BufReader::new(&stream).lines() {
+        let line = line?;
+        if line == "end" {
+            return Ok(panels);
+        }
+        if let Some((kind, serial)) = line.split_once(' ').and_then(|(kind, serial)| Some((PanelKind::from_name(kind)?, serial))) {
+            panels.push((kind, recording::unescape(serial)));
+        }
+    }
+    Err(Error::Io("simulator closed the connection".to_string()))
+}
+
+/// Connects to one simulated panel.
+pub(crate) fn open(address: &str, kind: PanelKind, serial: &str) -> Result<SimulatorTransport> {
+    let stream = TcpStream::connect(address)?;
+    stream.set_nodelay(true)?;
+    (&stream).write_all(format!("open {} {}\n", kind.name(), recording::escape(serial)).as_bytes())?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut answer = String::new();
+    reader.read_line(&mut answer)?;
+    match answer.trim_end() {
+        "ok" => (),
+        answer => return Err(Error::Io(answer.strip_prefix("error ").unwrap_or("simulator closed the connection").to_string()))
+    }
+    let (inputs_tx, inputs) = mpsc::channel();
+    let (reports_tx, reports) = mpsc::channel();
+    // the channels close when the simulator goes away, which reads then report as an unplugged panel
+    thread::spawn(move || {
+        for line in reader.lines() {
+            let Ok(line) = line else { break };
+            let sent = match line.split_once(' ') {
+                Some(("in", hex)) => inputs_tx.send(recording::from_hex(hex).unwrap_or_default()).is_ok(),
+                Some(("report", hex)) => reports_tx.send(recording::from_hex(hex).unwrap_or_default()).is_ok(),
+                _ => true
+            };
+            if !sent {
+                break;
+            }
+        }
+    });
+    Ok(SimulatorTransport { stream: Mutex::new(stream), inputs: Mutex::new(inputs), reports: Mutex::new(reports) })
+}
+
+/// Panel of a simulator, used in place of a hidapi device.
+pub(crate) struct SimulatorTransport {
+    stream: Mutex<TcpStream>,
+    inputs: Mutex<Receiver<Vec<u8>>>,
+    reports: Mutex<Receiver<Vec<u8>>>
+}
+
+impl SimulatorTransport {
+    fn send(&self, line: &str) -> Result<()> {
+        self.stream.lock().expect("simulator stream poisoned").write_all(line.as_bytes())?;
+        Ok(())
+    }
+}
+
+impl Drop for SimulatorTransport {
+    fn drop(&mut self) {
+        // the reader thread holds a clone of the stream, only a shutdown unplugs the panel
+        let _ = self.stream.lock().expect("simulator stream poisoned").shutdown(Shutdown::Both);
+    }
+}
+
+fn disconnected() -> Error {
+    Error::Io("simulator disconnected".to_string())
+}
+
+impl Transport for SimulatorTransport {
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> Result<usize> {
+        match self.inputs.lock().expect("simulator inputs poisoned").recv_timeout(Duration::from_millis(timeout.max(0) as u64)) {
+            Ok(report) => {
+                let size = report.len().min(buf.len());
+                buf[..size].copy_from_slice(&report[..size]);
+                Ok(size)
+            },
+            Err(RecvTimeoutError::Timeout) => Ok(0),
+            Err(RecvTimeoutError::Disconnected) => Err(disconnected())
+        }
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        self.send(&format!("feature {}\n", recording::to_hex(data)))
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        self.send(&format!("write {}\n", recording::to_hex(data)))?;
+        Ok(data.len())
+    }
+
+    fn get_input_report(&self, buf: &mut [u8]) -> Result<usize> {
+        self.send("report\n")?;
+        match self.reports.lock().expect("simulator reports poisoned").recv_timeout(REPORT_TIMEOUT) {
+            Ok(report) => {
+                let size = report.len().min(buf.len() - 1);
+                buf[1..=size].copy_from_slice(&report[..size]);
+                Ok(size + 1)
+            },
+            Err(RecvTimeoutError::Timeout) => Ok(0),
+            Err(RecvTimeoutError::Disconnected) => Err(disconnected())
+        }
+    }
+}
+
+/// State of one simulated panel as the application left it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VirtualPanel {
+    pub kind: PanelKind,
+    pub serial: String,
+    /// Input bits, laid out like `RadioPanelInputs` etc.
+    pub inputs: u32,
+    /// Whether an application drives the panel.
+    pub connected: bool,
+    /// Last feature report received, including the report id.
+    pub feature_report: Vec<u8>,
+    /// FIP LEDs S1-S6, up and down arrow, index 0 unused.
+    pub fip_leds: [bool; 9],
+    pub fip_page: u32,
+    /// Pages that have been sent an image.
+    pub fip_images: Vec<u32>
+}
+
+impl VirtualPanel {
+    fn new(kind: PanelKind, serial: &str) -> Self {
+        VirtualPanel {
+            kind,
+            serial: serial.to_string(),
+            inputs: 0,
+            connected: false,
+            feature_report: Vec::new(),
+            fip_leds: [false; 9],
+            fip_page: crate::direct_output::DEFAULT_PAGE,
+            fip_images: Vec::new()
+        }
+    }
+
+    /// Input report as the panel sends it.
+    pub fn report(&self) -> Vec<u8> {
+        let size = if self.kind == PanelKind::FlightInstrument { 2 } else { 3 };
+        self.inputs.to_le_bytes()[..size].to_vec()
+    }
+
+    /// Raw digit codes of display `index` (5 digits each, in report order), blank before the first report.
+    pub fn digits(&self, index: usize) -> [u8; 5] {
+        let mut digits = [0xff; 5];
+        if let Some(data) = self.feature_report.get(1 + index * 5..6 + index * 5) {
+            digits.copy_from_slice(data);
+        }
+        digits
+    }
+
+    /// Text of a display, see `digits`. Digits with a decimal point are followed by '.'.
+    pub fn display(&self, index: usize) -> String {
+        display_text(&self.digits(index))
+    }
+
+    /// Radio panel upper active, upper standby, lower active and lower standby displays.
+    pub fn radio_displays(&self) -> [String; 4] {
+        [self.display(0), self.display(1), self.display(2), self.display(3)]
+    }
+
+    /// Multi panel upper and lower displays.
+    pub fn multi_displays(&self) -> [String; 2] {
+        [self.display(0), self.display(1)]
+    }
+
+    pub fn multi_leds(&self) -> MultiPanelOutputLeds {
+        MultiPanelOutputLeds::from(self.feature_report.get(11).copied().unwrap_or(0))
+    }
+
+    /// Switch panel gear LEDs, laid out like `GearLedsStates`.
+    pub fn gear_leds(&self) -> u8 {
+        self.feature_report.get(1).copied().unwrap_or(0)
+    }
+
+    /// Applies a DirectOutput request sent to a simulated FIP.
+    fn direct_output(&mut self, data: &[u8]) {
+        let word = |index: usize| data.get(index * 4..index * 4 + 4).map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+        let (Some(page), Some(request)) = (word(1), word(6)) else { return };
+        match request {
+            // set led, request 4 is the down arrow
+            3 | 4 => if let (Some(led), Some(on)) = (word(7), word(8)) {
+                if let Some(state) = self.fip_leds.get_mut(led as usize) {
+                    *state = on != 0;
+                }
+            },
+            // add page, activating it when asked to
+            1 if word(8) == Some(1) => self.fip_page = page,
+            2 => self.fip_images.retain(|image| *image != page),
+            5 if !self.fip_images.contains(&page) => self.fip_images.push(page),
+            6 => self.fip_page = page,
+            _ => ()
+        }
+    }
+}
+
+/// Text of raw digit codes: 0-9, 0xD0-0xD9 with decimal point, 0xEE dash, anything else blank.
+pub fn display_text(digits: &[u8]) -> String {
+    let mut text = String::new();
+    for &code in digits {
+        match code {
+            0..=9 => text.push(char::from(b'0' + code)),
+            0xD0..=0xD9 => { text.push(char::from(b'0' + (code - 0xD0))); text.push('.') },
+            0xEE => text.push('-'),
+            _ => text.push(' ')
+        }
+    }
+    text
+}
+
+struct Shared {
+    panels: Vec<VirtualPanel>,
+    // connection of each driven panel, to push input reports
+    streams: Vec<Option<TcpStream>>
+}
+
+/// Simulated panels served to applications, see the `flightpanels-sim` binary.
+///
+/// Start it, then run the application with `FLIGHTPANELS_SIMULATOR` set to its address.
+/// Dropping it stops listening and unplugs every panel.
+pub struct Simulator {
+    shared: Arc<Mutex<Shared>>,
+    address: SocketAddr,
+    stopped: Arc<AtomicBool>,
+    listener: Option<JoinHandle<()>>
+}
+
+impl Simulator {
+    /// Listens on `address` (port 0 picks a free port) and offers the given panels.
+    pub fn start(address: &str, panels: &[(PanelKind, &str)]) -> Result<Self> {
+        let listener = TcpListener::bind(address)?;
+        let address = listener.local_addr()?;
+        let shared = Arc::new(Mutex::new(Shared {
+            panels: panels.iter().map(|(kind, serial)| VirtualPanel::new(*kind, serial)).collect(),
+            streams: panels.iter().map(|_| None).collect()
+        }));
+        let stopped = Arc::new(AtomicBool::new(false));
+        let server_shared = shared.clone();
+        let server_stopped = stopped.clone();
+        let listener = thread::spawn(move || {
+            for stream in listener.incoming() {
+                if server_stopped.load(Ordering::Relaxed) {
+                    break;
+                }
+                let Ok(stream) = stream else { continue };
+                let shared = server_shared.clone();
+                thread::spawn(move || serve(stream, &shared));
+            }
+        });
+        Ok(Simulator { shared, address, stopped, listener: Some(listener) })
+    }
+
+    pub fn address(&self) -> SocketAddr {
+        self.address
+    }
+
+    fn shared(&self) -> MutexGuard<'_, Shared> {
+        self.shared.lock().expect("simulator poisoned")
+    }
+
+    /// Current state of all panels.
+    pub fn panels(&self) -> Vec<VirtualPanel> {
+        self.shared().panels.clone()
+    }
+
+    /// Changes the inputs of panel `index` and sends them if they changed.
+    pub fn update(&self, index: usize, change: impl FnOnce(u32) -> u32) {
+        let mut shared = self.shared();
+        let Some(panel) = shared.panels.get_mut(index) else { return };
+        let inputs = change(panel.inputs);
+        if inputs == panel.inputs {
+            return;
+        }
+        panel.inputs = inputs;
+        let line = format!("in {}\n", recording::to_hex(&panel.report()));
+        if let Some(Some(stream)) = shared.streams.get(index) {
+            let _ = (&*stream).write_all(line.as_bytes());
+        }
+    }
+}
+
+impl Drop for Simulator {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::Relaxed);
+        // a connection of our own wakes the listener waiting in accept
+        let mut wake = self.address;
+        if wake.ip().is_unspecified() {
+            wake.set_ip(if wake.is_ipv4() { Ipv4Addr::LOCALHOST.into() } else { Ipv6Addr::LOCALHOST.into() });
+        }
+        if TcpStream::connect(wake).is_ok() {
+            if let Some(listener) = self.listener.take() {
+                let _ = listener.join();
+            }
+        }
+        for stream in self.shared().streams.iter().flatten() {
+            let _ = stream.shutdown(Shutdown::Both);
+        }
+    }
+}
+
+fn serve(stream: TcpStream, shared: &Mutex<Shared>) {
+    let lock = || shared.lock().expect("simulator poisoned");
+    let _ = stream.set_nodelay(true);
+    let mut lines = BufReader::new(&stream).lines();
+    let Some(Ok(request)) = lines.next() else { return };
+    let mut words = request.split(' ');
+    let index = match (words.next(), words.next().and_then(PanelKind::from_name), words.next().map(recording::unescape)) {
+        (Some("list"), _, _) => {
+            let mut answer: String = lock().panels.iter().map(|panel| format!("{} {}\n", panel.kind.name(), recording::escape(&panel.serial))).collect();
+            answer.push_str("end\n");
+            let _ = (&stream).write_all(answer.as_bytes());
+            return;
+        },
+        (Some("open"), Some(kind), Some(serial)) => {
+            let mut shared = lock();
+            let found = shared.panels.iter().position(|panel| panel.kind == kind && panel.serial == serial);
+            match found {
+                Some(index) if !shared.panels[index].connected => {
+                    shared.panels[index].connected = true;
+                    shared.streams[index] = stream.try_clone().ok();
+                    index
+                },
+                Some(_) => { let _ = (&stream).write_all(b"error panel already in use\n"); return },
+                None => { let _ = (&stream).write_all(b"error no such panel\n"); return }
+            }
+        },
+        _ => { let _ = (&stream).write_all(b"error invalid request\n"); return }
+    };
+    let _ = (&stream).write_all(b"ok\n");
+    for line in lines {
+        let Ok(line) = line else { break };
+        let (command, hex) = line.split_once(' ').unwrap_or((line.as_str(), ""));
+        let data = recording::from_hex(hex).unwrap_or_default();
+        let mut shared = lock();
+        match command {
+            "feature" => shared.panels[index].feature_report = data,
+            "write" => shared.panels[index].direct_output(&data),
+            "report" => {
+                let line = format!("report {}\n", recording::to_hex(&shared.panels[index].report()));
+                let _ = (&stream).write_all(line.as_bytes());
+            },
+            _ => ()
+        }
+    }
+    let mut shared = lock();
+    shared.panels[index].connected = false;
+    shared.streams[index] = None;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+    use crate::{DeviceId, InputData};
+    use crate::switch_panel::{self, LedColors, OutputCommands, SwitchPanelInputs};
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    fn wait_until(condition: impl Fn() -> bool) -> bool {
+        let deadline = Instant::now() + TIMEOUT;
+        while Instant::now() < deadline {
+            if condition() {
+                return true;
+            }
+            thread::sleep(Duration::from_millis(5));
+        }
+        false
+    }
+
+    #[test]
+    fn display_text() {
+        assert_eq!(super::display_text(&[1, 1, 0xD8, 2, 5]), "118.25");
+        assert_eq!(super::display_text(&[0xEE, 0x0A, 0xff, 4, 2]), "-  42");
+    }
+
+    #[test]
+    fn drives_simulated_panel() {
+        let simulator = Simulator::start("127.0.0.1:0", &[(PanelKind::Switch, "SIM 1"), (PanelKind::Radio, "SIM2")]).unwrap();
+        let address = simulator.address().to_string();
+        assert_eq!(list(&address).unwrap(), vec![(PanelKind::Switch, "SIM 1".to_string()), (PanelKind::Radio, "SIM2".to_string())]);
+        assert!(open(&address, PanelKind::Switch, "SIM").is_err());
+
+        simulator.update(0, |_| SwitchPanelInputs::new().with_battery(true).into());
+        let device = open(&address, PanelKind::Switch, "SIM 1").unwrap();
+        let (tx, rx) = mpsc::channel();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        let handle = switch_panel::SwitchPanel::receive_from(device, tx, cmd_rx);
+
+        let id = DeviceId::new(PanelKind::Switch, "");
+        assert_eq!(rx.recv_timeout(TIMEOUT), Ok(InputData::SwitchInputData(id.clone(), SwitchPanelInputs::new().with_battery(true))));
+        simulator.update(0, |inputs| SwitchPanelInputs::from(inputs).with_gear_down(true).into());
+        assert_eq!(rx.recv_timeout(TIMEOUT), Ok(InputData::SwitchInputData(id, SwitchPanelInputs::new().with_battery(true).with_gear_down(true))));
+
+        cmd_tx.send(OutputCommands::SetAllLedsTo(LedColors::Red)).unwrap();
+        assert!(wait_until(|| simulator.panels()[0].gear_leds() == 0b00111000));
+        assert!(simulator.panels()[0].connected);
+        handle.stop(false);
+        assert!(wait_until(|| !simulator.panels()[0].connected));
+    }
+
+    #[test]
+    fn drop_unplugs_panels() {
+        let simulator = Simulator::start("0.0.0.0:0", &[(PanelKind::Radio, "")]).unwrap();
+        let address = format!("127.0.0.1:{}", simulator.address().port());
+        let device = open(&address, PanelKind::Radio, "").unwrap();
+        drop(simulator);
+        assert!(list(&address).is_err());
+        assert_eq!(device.read_timeout(&mut [0; 4], 2000), Err(disconnected()));
+    }
+
+    #[test]
+    fn fip_requests() {
+        use crate::direct_output::set_led_request;
+        let mut panel = VirtualPanel::new(PanelKind::FlightInstrument, "");
+        panel.direct_output(&set_led_request(3, true));
+        panel.direct_output(&set_led_request(8, true));
+        assert!(panel.fip_leds[3] && panel.fip_leds[8] && !panel.fip_leds[1]);
+        #[cfg(feature = "experimental-fip-images")]
+        {
+            use crate::direct_output::{add_page_request, set_image_request};
+            panel.direct_output(&add_page_request(2, true));
+            panel.direct_output(&set_image_request(2, &[0; 6]));
+            assert_eq!(panel.fip_page, 2);
+            assert_eq!(panel.fip_images, vec![2]);
+        }
+    }
+}
@@ -2,80 +2,107 @@ use bitfield_struct::bitfield;
 use bitflags::bitflags;
 use hidapi::HidApi;
 use std::sync::mpsc::{Sender, Receiver};
-use std::result::Result;
-use std::thread;
-use std::time::Duration;
+use crate::PanelKind;
+use crate::device::{self, Panel, PanelHandle};
+use crate::error::Result;
+use crate::transport::Transport;
 
-const ID: (u16, u16) = (0x06A3, 0x0D67);
+pub(crate) const ID: (u16, u16) = (0x06A3, 0x0D67);
 
+/// Output state of a switch panel.
 pub struct SwitchPanel {
+    leds: u8,
+    dirty: bool
+}
+
+impl Default for SwitchPanel {
+    fn default() -> Self {
+        SwitchPanel { leds: 0, dirty: true }
+    }
 }
 
 impl SwitchPanel {
-    pub fn receive(api: &HidApi, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> Result<&'static str, &'static str> {
-        if let Ok(device) = api.open(ID.0, ID.1) {
-            thread::spawn(move || {
-                let mut input_buffer = [0u8; 4];
-                let mut current_leds: u8 = 0;
-                loop {
-                    match device.read_timeout(&mut input_buffer, 250) {
-                        Ok(_) => {
-                            tx.send(crate::InputData::SwitchInputData(
-                                SwitchPanelInputs::from(u32::from_le_bytes(input_buffer[0..4].try_into().expect("incorrect input length")))
-                            )).expect("could not send");
-                        },
-                        Err(_e) => ()
-                    }
-                    if let Ok(command) = rx.recv_timeout(Duration::from_millis(10)) {
-                        match command {
-                            OutputCommands::SetLeds(value) => {
-                                current_leds = value;
-                            }
-                            OutputCommands::SetAllLedsTo(color) => {
-                                current_leds = match color {
-                                    LedColors::Off => 0,
-                                    LedColors::Green => (GearLedsStates::UP_GREEN | GearLedsStates::LEFT_GREEN | GearLedsStates::RIGHT_GREEN).bits,
-                                    LedColors::Yellow => (GearLedsStates::UP_YELLOW | GearLedsStates::LEFT_YELLOW | GearLedsStates::RIGHT_YELLOW).bits,
-                                    LedColors::Red => (GearLedsStates::UP_RED | GearLedsStates::LEFT_RED | GearLedsStates::RIGHT_RED).bits,
-                                }
-                            },
-                            OutputCommands::SetUpLedTo(color) => {
-                                current_leds &= GearLedsStates::UP_MASK.bits;
-                                current_leds |= match color {
-                                    LedColors::Off => 0,
-                                    LedColors::Green => GearLedsStates::UP_GREEN.bits,
-                                    LedColors::Yellow => GearLedsStates::UP_YELLOW.bits,
-                                    LedColors::Red => GearLedsStates::UP_RED.bits
-                                }
-                            }
-                            OutputCommands::SetLeftLedTo(color) => {
-                                current_leds &= GearLedsStates::LEFT_MASK.bits;
-                                current_leds |= match color {
-                                    LedColors::Off => 0,
-                                    LedColors::Green => GearLedsStates::LEFT_GREEN.bits,
-                                    LedColors::Yellow => GearLedsStates::LEFT_YELLOW.bits,
-                                    LedColors::Red => GearLedsStates::LEFT_RED.bits
-                                }
-                            },
-                            OutputCommands::SetRightLedTo(color) => {
-                                current_leds &= GearLedsStates::RIGHT_MASK.bits;
-                                current_leds |= match color {
-                                    LedColors::Off => 0,
-                                    LedColors::Green => GearLedsStates::RIGHT_GREEN.bits,
-                                    LedColors::Yellow => GearLedsStates::RIGHT_YELLOW.bits,
-                                    LedColors::Red => GearLedsStates::RIGHT_RED.bits
-                                }
-                            }
-                        }
-                        device.send_feature_report(&[0, current_leds]);
-                    }
+    /// See [`PanelHandle`].
+    pub fn receive(api: &HidApi, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> Result<PanelHandle> {
+        let (id, device) = device::open_first::<Self>(api)?;
+        Ok(device::spawn::<Self, _>(id, device, tx, rx))
+    }
+
+    /// See [`PanelHandle`].
+    pub fn receive_from<T: Transport + 'static>(device: T, tx: Sender<crate::InputData>, rx: Receiver<OutputCommands>) -> PanelHandle {
+        device::spawn::<Self, T>(crate::DeviceId::new(PanelKind::Switch, ""), device, tx, rx)
+    }
+}
+
+impl Panel for SwitchPanel {
+    type Command = OutputCommands;
+    const KIND: PanelKind = PanelKind::Switch;
+    const ID: (u16, u16) = ID;
+
+    fn decode(device: &crate::DeviceId, report: [u8; 4]) -> crate::InputData {
+        crate::InputData::SwitchInputData(device.clone(), SwitchPanelInputs::from(u32::from_le_bytes(report)))
+    }
+
+    fn apply(&mut self, command: OutputCommands) -> Result<()> {
+        match command {
+            OutputCommands::SetLeds(value) => {
+                self.leds = value;
+            }
+            OutputCommands::SetAllLedsTo(color) => {
+                self.leds = match color {
+                    LedColors::Off => 0,
+                    LedColors::Green => (GearLedsStates::UP_GREEN | GearLedsStates::LEFT_GREEN | GearLedsStates::RIGHT_GREEN).bits,
+                    LedColors::Yellow => (GearLedsStates::UP_YELLOW | GearLedsStates::LEFT_YELLOW | GearLedsStates::RIGHT_YELLOW).bits,
+                    LedColors::Red => (GearLedsStates::UP_RED | GearLedsStates::LEFT_RED | GearLedsStates::RIGHT_RED).bits,
+                }
+            },
+            OutputCommands::SetUpLedTo(color) => {
+                self.leds &= GearLedsStates::UP_MASK.bits;
+                self.leds |= match color {
+                    LedColors::Off => 0,
+                    LedColors::Green => GearLedsStates::UP_GREEN.bits,
+                    LedColors::Yellow => GearLedsStates::UP_YELLOW.bits,
+                    LedColors::Red => GearLedsStates::UP_RED.bits
+                }
+            }
+            OutputCommands::SetLeftLedTo(color) => {
+                self.leds &= GearLedsStates::LEFT_MASK.bits;
+                self.leds |= match color {
+                    LedColors::Off => 0,
+                    LedColors::Green => GearLedsStates::LEFT_GREEN.bits,
+                    LedColors::Yellow => GearLedsStates::LEFT_YELLOW.bits,
+                    LedColors::Red => GearLedsStates::LEFT_RED.bits
+                }
+            },
+            OutputCommands::SetRightLedTo(color) => {
+                self.leds &= GearLedsStates::RIGHT_MASK.bits;
+                self.leds |= match color {
+                    LedColors::Off => 0,
+                    LedColors::Green => GearLedsStates::RIGHT_GREEN.bits,
+                    LedColors::Yellow => GearLedsStates::RIGHT_YELLOW.bits,
+                    LedColors::Red => GearLedsStates::RIGHT_RED.bits
                 }
-            });
-            return Ok("super")
+            }
         }
-        else {
-            return Err("Could not open device")
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn flush(&mut self, device: &dyn Transport) -> Result<()> {
+        if self.dirty {
+            device.send_feature_report(&[0, self.leds])?;
+            self.dirty = false;
         }
+        Ok(())
+    }
+
+    fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    fn dark(&mut self) {
+        self.leds = GearLedsStates::ALL_OFF.bits;
+        self.dirty = true;
     }
 }
 
@@ -103,8 +130,9 @@ pub struct SwitchPanelInputs {
     _pad: u16
 }
 
-#[derive(Debug, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u8)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum EngineSelection {
     Invalid = 0,
     OFF = 1,
@@ -132,6 +160,7 @@ bitflags! {
     }
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum LedColors {
     Off,
     Green,
@@ -139,6 +168,8 @@ pub enum LedColors {
     Red
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(clippy::enum_variant_names)]
 pub enum OutputCommands {
     SetLeds(u8),
     SetAllLedsTo(LedColors),
@@ -147,9 +178,9 @@ pub enum OutputCommands {
     SetRightLedTo(LedColors)
 }
 
-impl Into<u32> for EngineSelection {
-    fn into(self) -> u32 {
-        self as u8 as u32
+impl From<EngineSelection> for u32 {
+    fn from(value: EngineSelection) -> Self {
+        value as u8 as u32
     }
 }
 
@@ -164,4 +195,35 @@ impl From<u32> for EngineSelection {
             16..=u32::MAX => EngineSelection::START
         }
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::MockTransport;
+    use crate::transport::tests::mock_panel;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn mock_device() {
+        let inputs = SwitchPanelInputs::new().with_battery(true).with_engine_selector(EngineSelection::LEFT);
+        let (mock, cmd_tx, _inputs) = mock_panel(SwitchPanel::receive_from, PanelKind::Switch, vec![0, 0], inputs.into(), |id| crate::InputData::SwitchInputData(id, inputs));
+        cmd_tx.send(OutputCommands::SetAllLedsTo(LedColors::Green)).unwrap();
+        assert_eq!(mock.next_feature_report(Duration::from_secs(2)), Some(vec![0, 0b0000_0111]));
+    }
+
+    #[test]
+    fn goes_dark_on_stop() {
+        let mock = MockTransport::new();
+        let (tx, _rx) = mpsc::channel();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        let handle = SwitchPanel::receive_from(mock.clone(), tx, cmd_rx);
+        assert_eq!(mock.next_feature_report(Duration::from_secs(2)), Some(vec![0, 0]));
+        cmd_tx.send(OutputCommands::SetAllLedsTo(LedColors::Red)).unwrap();
+        assert_eq!(mock.next_feature_report(Duration::from_secs(2)), Some(vec![0, 0b0011_1000]));
+
+        handle.stop(true);
+        assert_eq!(mock.next_feature_report(Duration::from_secs(2)), Some(vec![0, 0]));
+    }
+}
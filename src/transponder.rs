@@ -0,0 +1,295 @@
+use std::fmt;
+use std::str::FromStr;
+use crate::error::Error;
+use crate::radio_stack::RadioEvent;
+use crate::segments::{BLANK, POINT as DOT};
+
+/*
+Transponder on a radio panel row (selector on XPDR)
+
+left display: the code, right aligned, with the decimal point on the digit being edited
+right display: the mode as a digit, the panel cannot show letters: 0 OFF, 1 SBY, 2 ON, 3 ALT,
+with the decimal point when the mode is being edited
+
+outer knob: moves between the four digits and the mode
+inner knob: changes the digit (0-7, wrapping) or the mode (OFF to ALT, not wrapping)
+swap button: moves to the next digit, or sends IDENT if set up that way
+
+a code is taken over when editing moves on to another digit, on IDENT or when the row is switched
+to another mode, so the codes passed while dialing a digit are never squawked, e.g. 7500 on the
+way from 7000 to 7600 (emergency codes 7500, 7600 and 7700 are flagged in SquawkChanged and shown
+with the decimal points of all four digits lit)
+*/
+
+/// Transponder code, four octal digits from 0000 to 7777.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Squawk(u16);
+
+impl Squawk {
+    pub const HIJACK: Squawk = Squawk(0o7500);
+    pub const RADIO_FAILURE: Squawk = Squawk(0o7600);
+    pub const EMERGENCY: Squawk = Squawk(0o7700);
+
+    /// The code as written, e.g. `Squawk::new(7700)`, `None` if it has a digit 8 or 9 or more than 4 digits.
+    pub fn new(code: u16) -> Option<Squawk> {
+        let digits = [code / 1000, code / 100 % 10, code / 10 % 10, code % 10];
+        if code > 9999 || digits.iter().any(|digit| *digit > 7) {
+            return None;
+        }
+        Some(Squawk(digits.iter().fold(0, |value, digit| value * 8 + digit)))
+    }
+
+    /// From the octal value, e.g. `Squawk::from_octal(0o7700)`.
+    pub fn from_octal(value: u16) -> Option<Squawk> {
+        (value <= 0o7777).then_some(Squawk(value))
+    }
+
+    pub fn octal(&self) -> u16 {
+        self.0
+    }
+
+    /// The code as written, e.g. 7700.
+    pub fn code(&self) -> u16 {
+        self.digits().iter().fold(0, |code, digit| code * 10 + *digit as u16)
+    }
+
+    /// Digits from left to right.
+    pub fn digits(&self) -> [u8; 4] {
+        [(self.0 >> 9) as u8 & 7, (self.0 >> 6) as u8 & 7, (self.0 >> 3) as u8 & 7, self.0 as u8 & 7]
+    }
+
+    /// The code with digit `index` (0 is the leftmost) replaced, digits above 7 wrap around.
+    pub fn with_digit(&self, index: usize, digit: u8) -> Squawk {
+        let shift = 9 - 3 * index.min(3) as u16;
+        Squawk(self.0 & !(7 << shift) | ((digit & 7) as u16) << shift)
+    }
+
+    pub fn emergency(&self) -> Option<Emergency> {
+        match *self {
+            Squawk::HIJACK => Some(Emergency::Hijack),
+            Squawk::RADIO_FAILURE => Some(Emergency::RadioFailure),
+            Squawk::EMERGENCY => Some(Emergency::General),
+            _ => None
+        }
+    }
+}
+
+impl Default for Squawk {
+    /// 7000, the conspicuity code used in most of Europe.
+    fn default() -> Self {
+        Squawk(0o7000)
+    }
+}
+
+impl fmt::Display for Squawk {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+impl FromStr for Squawk {
+    type Err = Error;
+
+    fn from_str(text: &str) -> Result<Self, Error> {
+        let invalid = || Error::InvalidDisplayText { display: "XPDR", text: text.to_string() };
+        // u16 parsing would take a sign, e.g. "+777"
+        if text.len() != 4 || !text.bytes().all(|b| (b'0'..=b'7').contains(&b)) {
+            return Err(invalid());
+        }
+        text.parse().ok().and_then(Squawk::new).ok_or_else(invalid)
+    }
+}
+
+/// Meaning of the emergency codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Emergency {
+    /// 7500
+    Hijack,
+    /// 7600
+    RadioFailure,
+    /// 7700
+    General
+}
+
+/// Transponder mode, shown as a digit on the right display since the panel has no letters.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum TransponderMode {
+    /// Shown as 0.
+    Off,
+    /// Shown as 1.
+    Standby,
+    /// Mode A, shown as 2.
+    On,
+    /// Mode C, with altitude, shown as 3.
+    Alt
+}
+
+impl TransponderMode {
+    const ALL: [TransponderMode; 4] = [TransponderMode::Off, TransponderMode::Standby, TransponderMode::On, TransponderMode::Alt];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransponderMode::Off => "OFF",
+            TransponderMode::Standby => "SBY",
+            TransponderMode::On => "ON",
+            TransponderMode::Alt => "ALT"
+        }
+    }
+
+    /// Digit shown for the mode.
+    fn digit(&self) -> u8 {
+        *self as u8
+    }
+}
+
+/// What the swap button does on a row showing the transponder.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum XpdrSwap {
+    /// Moves editing to the next digit.
+    NextDigit,
+    /// Sends IDENT, digits are then only chosen with the outer knob.
+    Ident
+}
+
+/// Position of the mode among the editing positions, after the four digits.
+const MODE: usize = 4;
+
+/// Transponder state of a `RadioStack`.
+pub(crate) struct Transponder {
+    pub(crate) squawk: Squawk,
+    /// Code being dialed, not squawked yet.
+    editing: Option<Squawk>,
+    /// Digit (0-3) or mode (4) the inner knob changes.
+    cursor: usize,
+    pub(crate) mode: TransponderMode,
+    pub(crate) swap: XpdrSwap
+}
+
+impl Default for Transponder {
+    fn default() -> Self {
+        Transponder { squawk: Squawk::default(), editing: None, cursor: 0, mode: TransponderMode::Standby, swap: XpdrSwap::NextDigit }
+    }
+}
+
+impl Transponder {
+    /// Code on the display.
+    pub(crate) fn shown(&self) -> Squawk {
+        self.editing.unwrap_or(self.squawk)
+    }
+
+    /// Sets the code from the application, dropping a code being dialed.
+    pub(crate) fn set_squawk(&mut self, squawk: Squawk) {
+        self.squawk = squawk;
+        self.editing = None;
+    }
+
+    /// Inner knob.
+    pub(crate) fn turn(&mut self, steps: i32, events: &mut Vec<RadioEvent>) {
+        if self.cursor == MODE {
+            let index = (self.mode as i32 + steps).clamp(0, TransponderMode::ALL.len() as i32 - 1);
+            let mode = TransponderMode::ALL[index as usize];
+            if mode != self.mode {
+                self.mode = mode;
+                events.push(RadioEvent::TransponderModeChanged(mode));
+            }
+        }
+        else {
+            let shown = self.shown();
+            let digit = (shown.digits()[self.cursor] as i32 + steps).rem_euclid(8);
+            self.editing = Some(shown.with_digit(self.cursor, digit as u8));
+        }
+    }
+
+    /// Outer knob, takes over the code dialed so far.
+    pub(crate) fn move_cursor(&mut self, steps: i32, events: &mut Vec<RadioEvent>) {
+        self.commit(events);
+        self.cursor = (self.cursor as i32 + steps).rem_euclid(MODE as i32 + 1) as usize;
+    }
+
+    pub(crate) fn press_swap(&mut self, events: &mut Vec<RadioEvent>) {
+        match self.swap {
+            XpdrSwap::NextDigit => self.move_cursor(1, events),
+            XpdrSwap::Ident => {
+                self.commit(events);
+                events.push(RadioEvent::Ident(self.squawk));
+            }
+        }
+    }
+
+    /// Squawks the code dialed, if any.
+    pub(crate) fn commit(&mut self, events: &mut Vec<RadioEvent>) {
+        if let Some(squawk) = self.editing.take() {
+            if squawk != self.squawk {
+                self.squawk = squawk;
+                events.push(RadioEvent::SquawkChanged { squawk, emergency: squawk.emergency() });
+            }
+        }
+    }
+
+    /// Left and right display.
+    pub(crate) fn displays(&self) -> ([u8; 5], [u8; 5]) {
+        let shown = self.shown();
+        let mut code = [BLANK; 5];
+        for (index, digit) in shown.digits().into_iter().enumerate() {
+            let marked = index == self.cursor || shown.emergency().is_some();
+            code[index + 1] = if marked { digit + DOT } else { digit };
+        }
+        let mut mode = [BLANK; 5];
+        mode[4] = if self.cursor == MODE { self.mode.digit() + DOT } else { self.mode.digit() };
+        (code, mode)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn squawk() {
+        assert_eq!(Squawk::new(7700), Some(Squawk::EMERGENCY));
+        assert_eq!(Squawk::new(1280), None);
+        assert_eq!(Squawk::new(17000), None);
+        assert_eq!(Squawk::new(0).map(|squawk| squawk.to_string()), Some("0000".to_string()));
+        assert_eq!(Squawk::new(7777).map(|squawk| squawk.octal()), Some(0o7777));
+        assert_eq!(Squawk::from_octal(0o10000), None);
+        assert_eq!("1234".parse::<Squawk>().map(|squawk| squawk.digits()), Ok([1, 2, 3, 4]));
+        assert_eq!("1238".parse::<Squawk>(), Err(Error::InvalidDisplayText { display: "XPDR", text: "1238".to_string() }));
+        assert_eq!("12a4".parse::<Squawk>(), Err(Error::InvalidDisplayText { display: "XPDR", text: "12a4".to_string() }));
+        assert!("123".parse::<Squawk>().is_err());
+        assert_eq!("+777".parse::<Squawk>(), Err(Error::InvalidDisplayText { display: "XPDR", text: "+777".to_string() }));
+        assert_eq!(Squawk::default().with_digit(1, 6).code(), 7600);
+        assert_eq!(Squawk::default().with_digit(1, 6).emergency(), Some(Emergency::RadioFailure));
+    }
+
+    #[test]
+    fn digit_editing() {
+        let mut transponder = Transponder::default();
+        let mut events = Vec::new();
+        transponder.move_cursor(1, &mut events);
+        // dialing 7000 to 7600 passes 7500, which is never squawked
+        transponder.turn(5, &mut events);
+        assert_eq!(transponder.shown().code(), 7500);
+        transponder.turn(1, &mut events);
+        assert_eq!(transponder.squawk.code(), 7000);
+        assert_eq!(transponder.displays().0, [BLANK, 7 + DOT, 6 + DOT, DOT, DOT]);
+        transponder.press_swap(&mut events);
+        assert_eq!(events, vec![RadioEvent::SquawkChanged { squawk: Squawk::RADIO_FAILURE, emergency: Some(Emergency::RadioFailure) }]);
+
+        events.clear();
+        transponder.turn(-1, &mut events);
+        assert_eq!(transponder.shown().code(), 7670);
+        transponder.move_cursor(-3, &mut events);
+        transponder.turn(3, &mut events);
+        transponder.turn(3, &mut events);
+        assert_eq!(transponder.mode, TransponderMode::Alt);
+        assert_eq!(transponder.displays(), ([BLANK, 7, 6, 7, 0], [BLANK, BLANK, BLANK, BLANK, 3 + DOT]));
+        transponder.swap = XpdrSwap::Ident;
+        transponder.press_swap(&mut events);
+        let squawk = Squawk::new(7670).unwrap();
+        assert_eq!(events, vec![
+            RadioEvent::SquawkChanged { squawk, emergency: None },
+            RadioEvent::TransponderModeChanged(TransponderMode::Alt),
+            RadioEvent::Ident(squawk)
+        ]);
+    }
+}
@@ -0,0 +1,243 @@
+use std::collections::VecDeque;
+use std::ffi::CStr;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use hidapi::{HidApi, HidDevice, HidResult};
+use crate::error::{Error, Result};
+
+/// Longest read (ms) on a hidapi handle that is shared with the writes.
+const SHARED_READ_TIMEOUT: i32 = 5;
+
+/// The operations the panel threads need from a device.
+///
+/// Implemented for hidapi devices ([`HidTransport`]) and for [`MockTransport`], so
+/// everything above the transport can run without hardware. Input reports are read
+/// in a thread of their own while the panel thread writes, hence `Sync`.
+pub trait Transport: Send + Sync {
+    /// Reads one input report, returns 0 if none arrived within `timeout` milliseconds.
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> Result<usize>;
+    fn send_feature_report(&self, data: &[u8]) -> Result<()>;
+    fn write(&self, data: &[u8]) -> Result<usize>;
+    /// Asks for the current input report, `buf[0]` holds the report id before and after.
+    ///
+    /// Returns the bytes read including the report id, 0 if there is no report.
+    fn get_input_report(&self, buf: &mut [u8]) -> Result<usize>;
+}
+
+/// A hidapi device.
+///
+/// A hidapi handle must not be used by two threads at once, so reads go through a
+/// second handle on the same device where the platform lets it be opened twice
+/// (hidraw on Linux, Windows). Elsewhere reads share the handle with the writes and
+/// block for at most 5 ms at a time, which is then how long output can be held up.
+pub struct HidTransport {
+    device: Mutex<HidDevice>,
+    reader: Option<Mutex<HidDevice>>
+}
+
+impl HidTransport {
+    /// Opens the device at `path`, twice if possible.
+    pub fn open(api: &HidApi, path: &CStr) -> HidResult<Self> {
+        let device = api.open_path(path)?;
+        let reader = api.open_path(path).ok().map(Mutex::new);
+        Ok(HidTransport { device: Mutex::new(device), reader })
+    }
+
+    fn device(&self) -> MutexGuard<'_, HidDevice> {
+        self.device.lock().expect("hid device poisoned")
+    }
+}
+
+impl From<HidDevice> for HidTransport {
+    /// Uses a single handle for reads and writes.
+    fn from(device: HidDevice) -> Self {
+        HidTransport { device: Mutex::new(device), reader: None }
+    }
+}
+
+impl Transport for HidTransport {
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> Result<usize> {
+        match &self.reader {
+            Some(reader) => Ok(reader.lock().expect("hid device poisoned").read_timeout(buf, timeout)?),
+            None => {
+                // hidapi blocks for good on a negative timeout
+                let timeout = if (0..SHARED_READ_TIMEOUT).contains(&timeout) { timeout } else { SHARED_READ_TIMEOUT };
+                Ok(self.device().read_timeout(buf, timeout)?)
+            }
+        }
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        Ok(self.device().send_feature_report(data)?)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        Ok(self.device().write(data)?)
+    }
+
+    fn get_input_report(&self, buf: &mut [u8]) -> Result<usize> {
+        Ok(self.device().get_input_report(buf)?)
+    }
+}
+
+impl<T: Transport + ?Sized> Transport for Box<T> {
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> Result<usize> {
+        (**self).read_timeout(buf, timeout)
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        (**self).send_feature_report(data)
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        (**self).write(data)
+    }
+
+    fn get_input_report(&self, buf: &mut [u8]) -> Result<usize> {
+        (**self).get_input_report(buf)
+    }
+}
+
+#[derive(Default)]
+struct MockState {
+    inputs: VecDeque<Vec<u8>>,
+    feature_reports: VecDeque<Vec<u8>>,
+    writes: VecDeque<Vec<u8>>,
+    input_report: Option<Vec<u8>>,
+    disconnected: bool
+}
+
+/// In-memory device that plays back scripted input reports and captures everything sent to it.
+///
+/// Clones share the same state: hand one clone to a panel and keep the other to script it.
+#[derive(Clone, Default)]
+pub struct MockTransport {
+    state: Arc<(Mutex<MockState>, Condvar)>
+}
+
+impl MockTransport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Queues an input report, it is returned by the next read and by `get_input_report` from then on.
+    pub fn push_input(&self, report: &[u8]) {
+        self.update(|state| {
+            state.inputs.push_back(report.to_vec());
+            state.input_report = Some(report.to_vec());
+        });
+    }
+
+    /// Sets the report returned by `get_input_report`, without the report id.
+    pub fn set_input_report(&self, report: &[u8]) {
+        self.update(|state| state.input_report = Some(report.to_vec()));
+    }
+
+    /// Waits up to `timeout` for the next feature report sent to the device.
+    pub fn next_feature_report(&self, timeout: Duration) -> Option<Vec<u8>> {
+        self.wait_for(timeout, |state| state.feature_reports.pop_front())
+    }
+
+    /// Waits up to `timeout` for the next data written to the device.
+    pub fn next_write(&self, timeout: Duration) -> Option<Vec<u8>> {
+        self.wait_for(timeout, |state| state.writes.pop_front())
+    }
+
+    /// Waits up to `timeout` until every queued input report has been read, false if some are left.
+    pub fn wait_until_read(&self, timeout: Duration) -> bool {
+        self.wait_for(timeout, |state| if state.inputs.is_empty() || state.disconnected { Some(()) } else { None }).is_some()
+    }
+
+    /// Simulates unplugging the device, every following operation fails.
+    pub fn disconnect(&self) {
+        self.update(|state| state.disconnected = true);
+    }
+
+    fn update<R>(&self, f: impl FnOnce(&mut MockState) -> R) -> R {
+        let (lock, condvar) = &*self.state;
+        let result = f(&mut lock.lock().expect("mock state poisoned"));
+        condvar.notify_all();
+        result
+    }
+
+    fn wait_for<R>(&self, timeout: Duration, mut f: impl FnMut(&mut MockState) -> Option<R>) -> Option<R> {
+        let (lock, condvar) = &*self.state;
+        let deadline = Instant::now() + timeout;
+        let mut state = lock.lock().expect("mock state poisoned");
+        loop {
+            if let Some(result) = f(&mut state) {
+                // taking something out may be what another waiter waits for
+                condvar.notify_all();
+                return Some(result);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            state = condvar.wait_timeout(state, deadline - now).expect("mock state poisoned").0;
+        }
+    }
+}
+
+fn disconnected() -> Error {
+    Error::Io("device disconnected".to_string())
+}
+
+impl Transport for MockTransport {
+    fn read_timeout(&self, buf: &mut [u8], timeout: i32) -> Result<usize> {
+        let timeout = Duration::from_millis(timeout.max(0) as u64);
+        match self.wait_for(timeout, |state| if state.disconnected { Some(None) } else { state.inputs.pop_front().map(Some) }) {
+            Some(Some(report)) => {
+                let size = report.len().min(buf.len());
+                buf[..size].copy_from_slice(&report[..size]);
+                Ok(size)
+            },
+            Some(None) => Err(disconnected()),
+            None => Ok(0)
+        }
+    }
+
+    fn send_feature_report(&self, data: &[u8]) -> Result<()> {
+        self.update(|state| if state.disconnected { Err(disconnected()) } else { state.feature_reports.push_back(data.to_vec()); Ok(()) })
+    }
+
+    fn write(&self, data: &[u8]) -> Result<usize> {
+        self.update(|state| if state.disconnected { Err(disconnected()) } else { state.writes.push_back(data.to_vec()); Ok(data.len()) })
+    }
+
+    fn get_input_report(&self, buf: &mut [u8]) -> Result<usize> {
+        self.update(|state| match &state.input_report {
+            _ if state.disconnected => Err(disconnected()),
+            Some(report) => {
+                let size = report.len().min(buf.len() - 1);
+                buf[1..=size].copy_from_slice(&report[..size]);
+                Ok(size + 1)
+            },
+            None => Ok(0)
+        })
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::{DeviceId, InputData, PanelKind};
+    use crate::device::PanelHandle;
+    use std::sync::mpsc::{self, Receiver, Sender};
+
+    const TIMEOUT: Duration = Duration::from_secs(2);
+
+    /// Runs a HID panel on a mock and checks its first feature report and the decoding of `inputs`.
+    ///
+    /// Returns the mock, the command sender and the inputs, which keep the panel running while they are held.
+    pub(crate) fn mock_panel<C>(receive_from: fn(MockTransport, Sender<InputData>, Receiver<C>) -> PanelHandle, kind: PanelKind, first_report: Vec<u8>, inputs: u32, decoded: impl FnOnce(DeviceId) -> InputData) -> (MockTransport, Sender<C>, Receiver<InputData>) {
+        let mock = MockTransport::new();
+        let (tx, rx) = mpsc::channel();
+        let (cmd_tx, cmd_rx) = mpsc::channel();
+        receive_from(mock.clone(), tx, cmd_rx);
+        assert_eq!(mock.next_feature_report(TIMEOUT), Some(first_report));
+        mock.push_input(&inputs.to_le_bytes());
+        assert_eq!(rx.recv_timeout(TIMEOUT), Ok(decoded(DeviceId::new(kind, ""))));
+        (mock, cmd_tx, rx)
+    }
+}